@@ -1,5 +1,9 @@
+#[cfg(feature = "evdev")]
+extern crate evdev;
 extern crate libc;
 #[cfg(feature = "wayland")]
 extern crate memmap2;
+#[cfg(feature = "serde")]
+extern crate serde;
 
 pub mod xkb;
@@ -0,0 +1,106 @@
+use super::FilterEvent;
+use crate::xkb::{KeyDirection, Keycode};
+use std::time::Duration;
+
+/// BounceKeys accessibility filter.
+///
+/// With BounceKeys, a key press which follows the release of the same key
+/// within a given delay is ignored, along with its release, so that
+/// unintended repeated presses (e.g. caused by tremors) are dropped.
+///
+/// The filter is driven by the timestamps of the key events, which may
+/// come from any monotonic clock, and does not read the time by itself.
+pub struct BounceKeys {
+    delay: Duration,
+    released: Vec<(Keycode, Duration)>,
+    rejected: Vec<Keycode>,
+}
+
+impl BounceKeys {
+    /// Create a BounceKeys filter with the given debounce delay.
+    #[must_use]
+    pub fn new(delay: Duration) -> BounceKeys {
+        BounceKeys {
+            delay,
+            released: Vec::new(),
+            rejected: Vec::new(),
+        }
+    }
+
+    /// Get the debounce delay.
+    #[must_use]
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Set the debounce delay.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Filter a key event which happened at time `time`.
+    ///
+    /// A key press is rejected if the same key was released less than the
+    /// delay before, and accepted otherwise. A key release is rejected if
+    /// the press was rejected, and accepted otherwise.
+    pub fn filter(&mut self, key: Keycode, direction: KeyDirection, time: Duration) -> FilterEvent {
+        let delay = self.delay;
+        self.released
+            .retain(|&(_, released)| time.saturating_sub(released) < delay);
+        match direction {
+            KeyDirection::Down => {
+                if self.released.iter().any(|&(k, _)| k == key) {
+                    if !self.rejected.contains(&key) {
+                        self.rejected.push(key);
+                    }
+                    FilterEvent::Rejected(key, KeyDirection::Down)
+                } else {
+                    FilterEvent::Accepted(key, KeyDirection::Down)
+                }
+            }
+            KeyDirection::Up => {
+                if let Some(pos) = self.rejected.iter().position(|&k| k == key) {
+                    self.rejected.remove(pos);
+                    FilterEvent::Rejected(key, KeyDirection::Up)
+                } else {
+                    self.released.retain(|&(k, _)| k != key);
+                    self.released.push((key, time));
+                    FilterEvent::Accepted(key, KeyDirection::Up)
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn bounce_keys_reject_quick_presses() {
+    let key = Keycode::new(38);
+    let other = Keycode::new(39);
+    let mut bounce_keys = BounceKeys::new(Duration::from_millis(100));
+    let mut filter =
+        |key, direction, ms| bounce_keys.filter(key, direction, Duration::from_millis(ms));
+    assert_eq!(
+        filter(key, KeyDirection::Down, 1000),
+        FilterEvent::Accepted(key, KeyDirection::Down)
+    );
+    assert_eq!(
+        filter(key, KeyDirection::Up, 1050),
+        FilterEvent::Accepted(key, KeyDirection::Up)
+    );
+    assert_eq!(
+        filter(key, KeyDirection::Down, 1080),
+        FilterEvent::Rejected(key, KeyDirection::Down)
+    );
+    assert_eq!(
+        filter(other, KeyDirection::Down, 1090),
+        FilterEvent::Accepted(other, KeyDirection::Down)
+    );
+    assert_eq!(
+        filter(key, KeyDirection::Up, 1100),
+        FilterEvent::Rejected(key, KeyDirection::Up)
+    );
+    assert_eq!(
+        filter(key, KeyDirection::Down, 1200),
+        FilterEvent::Accepted(key, KeyDirection::Down)
+    );
+}
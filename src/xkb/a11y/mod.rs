@@ -0,0 +1,30 @@
+//! Accessibility features implemented on top of `State`.
+//!
+//! libxkbcommon does not implement the AccessX features of the X server.
+//! The components of this module provide them to compositors and other
+//! programs which process key events with `State::update_key()`.
+
+mod bounce;
+mod mouse;
+mod slow;
+mod sticky;
+
+pub use self::bounce::*;
+pub use self::mouse::*;
+pub use self::slow::*;
+pub use self::sticky::*;
+
+use crate::xkb::{KeyDirection, Keycode};
+
+/// Decision of a timestamp-driven key event filter, such as `SlowKeys` or
+/// `BounceKeys`, on a key event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FilterEvent {
+    /// The key event is accepted, and should be fed to
+    /// `State::update_key()`.
+    Accepted(Keycode, KeyDirection),
+    /// The key event is dropped.
+    Rejected(Keycode, KeyDirection),
+    /// The key press is delayed, and will be accepted or rejected later.
+    Pending(Keycode),
+}
@@ -0,0 +1,452 @@
+use crate::xkb::keysyms::*;
+use crate::xkb::{KeyDirection, Keycode, State};
+use std::time::Duration;
+
+/// Pointer events emitted by `MouseKeys`, to be injected by the caller.
+///
+/// Buttons are numbered as in X11: 1 is the left button, 2 the middle one
+/// and 3 the right one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerEvent {
+    /// Relative pointer motion.
+    Motion { dx: f64, dy: f64 },
+    /// A button is pressed.
+    ButtonPress(u32),
+    /// A button is released.
+    ButtonRelease(u32),
+    /// A button is pressed and locked down until unlocked.
+    ButtonLock(u32),
+    /// A locked button is released.
+    ButtonUnlock(u32),
+}
+
+/// Acceleration parameters of `MouseKeys`, as in the MouseKeysAccel
+/// control of XKB.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MouseKeysAccel {
+    /// Delay between the initial motion and the first repeated motion.
+    pub delay: Duration,
+    /// Delay between repeated motions.
+    pub interval: Duration,
+    /// Number of repeated motions until the maximum speed is reached.
+    pub time_to_max: u32,
+    /// Maximum motion, in units per repeated motion.
+    pub max_speed: f64,
+    /// Shape of the acceleration curve: 0 accelerates linearly, positive
+    /// values accelerate slowly at first, negative values quickly at first.
+    /// The valid range is ]-1000, 1000].
+    pub curve: i32,
+}
+
+impl Default for MouseKeysAccel {
+    /// The default parameters of the X server.
+    fn default() -> MouseKeysAccel {
+        MouseKeysAccel {
+            delay: Duration::from_millis(160),
+            interval: Duration::from_millis(40),
+            time_to_max: 30,
+            max_speed: 30.0,
+            curve: 500,
+        }
+    }
+}
+
+impl MouseKeysAccel {
+    /// Get the motion factor of the `count`-th repeated motion.
+    fn factor(&self, count: u32) -> f64 {
+        if count >= self.time_to_max {
+            return self.max_speed;
+        }
+        let curve = 1.0 + f64::from(self.curve.clamp(-999, 1000)) * 0.001;
+        let scale = self.max_speed / f64::from(self.time_to_max).powf(curve);
+        (scale * f64::from(count).powf(curve)).max(1.0)
+    }
+}
+
+/// Action bound to a keypad keysym.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum KeypadAction {
+    Move(i32, i32),
+    Click,
+    DoubleClick,
+    SetButton(u32),
+    Lock,
+    Unlock,
+}
+
+#[allow(non_upper_case_globals)]
+fn keypad_action(keysym: u32) -> Option<KeypadAction> {
+    Some(match keysym {
+        KEY_KP_1 | KEY_KP_End => KeypadAction::Move(-1, 1),
+        KEY_KP_2 | KEY_KP_Down => KeypadAction::Move(0, 1),
+        KEY_KP_3 | KEY_KP_Next => KeypadAction::Move(1, 1),
+        KEY_KP_4 | KEY_KP_Left => KeypadAction::Move(-1, 0),
+        KEY_KP_6 | KEY_KP_Right => KeypadAction::Move(1, 0),
+        KEY_KP_7 | KEY_KP_Home => KeypadAction::Move(-1, -1),
+        KEY_KP_8 | KEY_KP_Up => KeypadAction::Move(0, -1),
+        KEY_KP_9 | KEY_KP_Prior => KeypadAction::Move(1, -1),
+        KEY_KP_5 | KEY_KP_Begin => KeypadAction::Click,
+        KEY_KP_Add => KeypadAction::DoubleClick,
+        KEY_KP_Divide => KeypadAction::SetButton(1),
+        KEY_KP_Multiply => KeypadAction::SetButton(2),
+        KEY_KP_Subtract => KeypadAction::SetButton(3),
+        KEY_KP_0 | KEY_KP_Insert => KeypadAction::Lock,
+        KEY_KP_Decimal | KEY_KP_Delete => KeypadAction::Unlock,
+        _ => return None,
+    })
+}
+
+/// MouseKeys accessibility component.
+///
+/// While enabled, MouseKeys turns the keypad into a pointing device, with
+/// the bindings of the XKB `mousekeys` compatibility map:
+///
+/// - the keypad arrows (`KP_1`...`KP_9` but `KP_5`) move the pointer,
+///   with acceleration while held;
+/// - `KP_5` clicks the default button, and `KP_Add` double-clicks it;
+/// - `KP_Divide`, `KP_Multiply` and `KP_Subtract` select the left, middle
+///   and right button as the default button;
+/// - `KP_0` locks the default button down and `KP_Decimal` releases it.
+///
+/// Keysyms are looked up through the current `State`, so that both the
+/// Num Lock and non Num Lock keysyms of the keypad are recognized. Keys
+/// consumed by MouseKeys must not be fed to `State::update_key()`.
+///
+/// The motion is driven by the timestamps of the key events, which may
+/// come from any monotonic clock; the caller must call `poll()` at the
+/// time returned by `next_deadline()` to get the repeated motions.
+pub struct MouseKeys {
+    enabled: bool,
+    accel: MouseKeysAccel,
+    button: u32,
+    /// Keys consumed by MouseKeys, with the action they triggered and the
+    /// default button when they were pressed.
+    keys: Vec<(Keycode, KeypadAction, u32)>,
+    locked: Vec<u32>,
+    /// Time of the next motion, and number of repeated motions so far.
+    motion: Option<(Duration, u32)>,
+}
+
+impl Default for MouseKeys {
+    fn default() -> MouseKeys {
+        MouseKeys::new()
+    }
+}
+
+impl MouseKeys {
+    /// Create an enabled MouseKeys component, with the default acceleration
+    /// parameters and the left button as the default button.
+    #[must_use]
+    pub fn new() -> MouseKeys {
+        MouseKeys {
+            enabled: true,
+            accel: MouseKeysAccel::default(),
+            button: 1,
+            keys: Vec::new(),
+            locked: Vec::new(),
+            motion: None,
+        }
+    }
+
+    /// Whether MouseKeys is enabled.
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable MouseKeys.
+    ///
+    /// Disabling MouseKeys stops the motion and releases the locked buttons.
+    pub fn set_enabled(&mut self, enabled: bool) -> Vec<PointerEvent> {
+        self.enabled = enabled;
+        if enabled {
+            return Vec::new();
+        }
+        self.motion = None;
+        self.locked
+            .drain(..)
+            .map(PointerEvent::ButtonUnlock)
+            .collect()
+    }
+
+    /// Get the acceleration parameters.
+    #[must_use]
+    pub fn accel(&self) -> MouseKeysAccel {
+        self.accel
+    }
+
+    /// Set the acceleration parameters.
+    pub fn set_accel(&mut self, accel: MouseKeysAccel) {
+        self.accel = accel;
+    }
+
+    /// Get the default button, which is clicked and locked by the keypad.
+    #[must_use]
+    pub fn default_button(&self) -> u32 {
+        self.button
+    }
+
+    /// Process a key event which happened at time `time`.
+    ///
+    /// Returns the pointer events to inject if the key event is consumed by
+    /// MouseKeys, or `None` if it must be fed to `State::update_key()`.
+    pub fn update_key(
+        &mut self,
+        state: &State,
+        key: Keycode,
+        direction: KeyDirection,
+        time: Duration,
+    ) -> Option<Vec<PointerEvent>> {
+        match direction {
+            KeyDirection::Down => {
+                if self.keys.iter().any(|&(k, ..)| k == key) {
+                    // Key repeat: motion is repeated by poll().
+                    return Some(Vec::new());
+                }
+                if !self.enabled {
+                    return None;
+                }
+                let action = keypad_action(state.key_get_one_sym(key).raw())?;
+                self.keys.push((key, action, self.button));
+                Some(self.press(action, time))
+            }
+            KeyDirection::Up => {
+                let pos = self.keys.iter().position(|&(k, ..)| k == key)?;
+                let (_, action, button) = self.keys.remove(pos);
+                Some(self.release(action, button))
+            }
+        }
+    }
+
+    fn press(&mut self, action: KeypadAction, time: Duration) -> Vec<PointerEvent> {
+        match action {
+            KeypadAction::Move(..) => {
+                if self.motion.is_none() {
+                    self.motion = Some((time + self.accel.delay, 0));
+                }
+                self.motion_event(1.0).into_iter().collect()
+            }
+            KeypadAction::Click => vec![PointerEvent::ButtonPress(self.button)],
+            KeypadAction::DoubleClick => vec![
+                PointerEvent::ButtonPress(self.button),
+                PointerEvent::ButtonRelease(self.button),
+                PointerEvent::ButtonPress(self.button),
+                PointerEvent::ButtonRelease(self.button),
+            ],
+            KeypadAction::SetButton(button) => {
+                self.button = button;
+                Vec::new()
+            }
+            KeypadAction::Lock => {
+                if self.locked.contains(&self.button) {
+                    return Vec::new();
+                }
+                self.locked.push(self.button);
+                vec![PointerEvent::ButtonLock(self.button)]
+            }
+            KeypadAction::Unlock => match self.locked.iter().position(|&b| b == self.button) {
+                Some(pos) => vec![PointerEvent::ButtonUnlock(self.locked.remove(pos))],
+                None => Vec::new(),
+            },
+        }
+    }
+
+    /// Release the key of an action, pressed while `button` was the default
+    /// button.
+    fn release(&mut self, action: KeypadAction, button: u32) -> Vec<PointerEvent> {
+        match action {
+            KeypadAction::Move(..) => {
+                if self.direction() == (0, 0) {
+                    self.motion = None;
+                }
+                Vec::new()
+            }
+            KeypadAction::Click => vec![PointerEvent::ButtonRelease(button)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Get the direction of motion from the movement keys held.
+    fn direction(&self) -> (i32, i32) {
+        let (dx, dy) = self
+            .keys
+            .iter()
+            .filter_map(|&(_, action, _)| match action {
+                KeypadAction::Move(dx, dy) => Some((dx, dy)),
+                _ => None,
+            })
+            .fold((0, 0), |(x, y), (dx, dy)| (x + dx, y + dy));
+        (dx.signum(), dy.signum())
+    }
+
+    fn motion_event(&self, factor: f64) -> Option<PointerEvent> {
+        match self.direction() {
+            (0, 0) => None,
+            (dx, dy) => Some(PointerEvent::Motion {
+                dx: f64::from(dx) * factor,
+                dy: f64::from(dy) * factor,
+            }),
+        }
+    }
+
+    /// Get the repeated motions due at time `now`.
+    pub fn poll(&mut self, now: Duration) -> Vec<PointerEvent> {
+        let mut events = Vec::new();
+        while let Some((next, count)) = self.motion {
+            if next > now {
+                break;
+            }
+            let count = count + 1;
+            events.extend(self.motion_event(self.accel.factor(count)));
+            self.motion = Some((
+                next + self.accel.interval.max(Duration::from_millis(1)),
+                count,
+            ));
+        }
+        events
+    }
+
+    /// Get the time of the next repeated motion, or `None` if the pointer
+    /// is not moving.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.motion.map(|(next, _)| next)
+    }
+}
+
+#[test]
+fn mouse_keys_accel_curve() {
+    let accel = MouseKeysAccel::default();
+    assert_eq!(accel.factor(1), 1.0);
+    assert!((accel.factor(10) - 30.0 * (10.0f64 / 30.0).powf(1.5)).abs() < 1e-9);
+    assert_eq!(accel.factor(30), 30.0);
+    assert_eq!(accel.factor(100), 30.0);
+
+    let linear = MouseKeysAccel { curve: 0, ..accel };
+    assert!((linear.factor(15) - 15.0).abs() < 1e-9);
+    // Negative curves accelerate quickly at first.
+    let fast = MouseKeysAccel {
+        curve: -500,
+        ..accel
+    };
+    assert!(fast.factor(5) > linear.factor(5) && linear.factor(5) > accel.factor(5));
+}
+
+#[cfg(test)]
+fn keypad() -> State {
+    use crate::xkb::{Context, Keymap, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    State::new(&keymap)
+}
+
+#[test]
+fn mouse_keys_motion() {
+    let state = keypad();
+    let kp6 = state.get_keymap().key_by_name("KP6").unwrap();
+    let kp8 = state.get_keymap().key_by_name("KP8").unwrap();
+    let ms = Duration::from_millis;
+    let mut mouse = MouseKeys::new();
+    let motion = |dx, dy| PointerEvent::Motion { dx, dy };
+
+    let events = mouse.update_key(&state, kp6, KeyDirection::Down, ms(0));
+    assert_eq!(events, Some(vec![motion(1.0, 0.0)]));
+    assert_eq!(mouse.next_deadline(), Some(ms(160)));
+    assert!(mouse.poll(ms(159)).is_empty());
+
+    // A late poll catches up with all the motions due.
+    let accel = mouse.accel();
+    let events = mouse.poll(ms(240));
+    let expected: Vec<_> = (1..=3)
+        .map(|count| motion(accel.factor(count), 0.0))
+        .collect();
+    assert_eq!(events, expected);
+    assert_eq!(mouse.next_deadline(), Some(ms(280)));
+
+    // Key repeats are consumed without motion, diagonals combine.
+    assert_eq!(
+        mouse.update_key(&state, kp6, KeyDirection::Down, ms(250)),
+        Some(vec![])
+    );
+    let events = mouse
+        .update_key(&state, kp8, KeyDirection::Down, ms(260))
+        .unwrap();
+    assert_eq!(events, [motion(1.0, -1.0)]);
+    assert_eq!(
+        mouse.poll(ms(280)),
+        [motion(accel.factor(4), -accel.factor(4))]
+    );
+
+    mouse.update_key(&state, kp6, KeyDirection::Up, ms(290));
+    assert!(mouse.next_deadline().is_some());
+    mouse.update_key(&state, kp8, KeyDirection::Up, ms(300));
+    assert_eq!(mouse.next_deadline(), None);
+    assert!(mouse.poll(ms(1000)).is_empty());
+
+    // Other keys are left to the state.
+    let a = state.get_keymap().key_by_name("AC01").unwrap();
+    assert_eq!(mouse.update_key(&state, a, KeyDirection::Down, ms(0)), None);
+}
+
+#[test]
+fn mouse_keys_buttons() {
+    use PointerEvent::*;
+
+    let state = keypad();
+    let key = |name| state.get_keymap().key_by_name(name).unwrap();
+    let ms = Duration::from_millis;
+    let mut mouse = MouseKeys::new();
+    let send = |mouse: &mut MouseKeys, name, direction| {
+        mouse
+            .update_key(&state, key(name), direction, ms(0))
+            .unwrap()
+    };
+
+    assert_eq!(
+        send(&mut mouse, "KP5", KeyDirection::Down),
+        [ButtonPress(1)]
+    );
+    // Changing the default button does not affect the held click.
+    send(&mut mouse, "KPSU", KeyDirection::Down);
+    assert_eq!(mouse.default_button(), 3);
+    assert_eq!(
+        send(&mut mouse, "KP5", KeyDirection::Up),
+        [ButtonRelease(1)]
+    );
+    send(&mut mouse, "KPSU", KeyDirection::Up);
+
+    assert_eq!(
+        send(&mut mouse, "KPAD", KeyDirection::Down),
+        [
+            ButtonPress(3),
+            ButtonRelease(3),
+            ButtonPress(3),
+            ButtonRelease(3)
+        ]
+    );
+    assert_eq!(send(&mut mouse, "KP0", KeyDirection::Down), [ButtonLock(3)]);
+    send(&mut mouse, "KP0", KeyDirection::Up);
+    assert!(send(&mut mouse, "KP0", KeyDirection::Down).is_empty());
+    send(&mut mouse, "KP0", KeyDirection::Up);
+    send(&mut mouse, "KPDV", KeyDirection::Down);
+    assert_eq!(send(&mut mouse, "KP0", KeyDirection::Down), [ButtonLock(1)]);
+    assert_eq!(
+        send(&mut mouse, "KPDL", KeyDirection::Down),
+        [ButtonUnlock(1)]
+    );
+    assert_eq!(mouse.set_enabled(false), [ButtonUnlock(3)]);
+    assert_eq!(
+        mouse.update_key(&state, key("KP5"), KeyDirection::Down, ms(0)),
+        None
+    );
+}
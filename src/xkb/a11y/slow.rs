@@ -0,0 +1,143 @@
+use super::FilterEvent;
+use crate::xkb::{KeyDirection, Keycode};
+use std::time::Duration;
+
+/// SlowKeys accessibility filter.
+///
+/// With SlowKeys, a key must be held for a given delay before its press
+/// is accepted, so that accidental brief presses are ignored.
+///
+/// The filter is driven by the timestamps of the key events, which may
+/// come from any monotonic clock, and does not read the time by itself.
+/// Since a pending press is accepted once its delay elapses, the caller
+/// must also call `poll()` at the time returned by `next_deadline()`.
+pub struct SlowKeys {
+    delay: Duration,
+    pending: Vec<(Keycode, Duration)>,
+    accepted: Vec<Keycode>,
+}
+
+impl SlowKeys {
+    /// Create a SlowKeys filter with the given acceptance delay.
+    #[must_use]
+    pub fn new(delay: Duration) -> SlowKeys {
+        SlowKeys {
+            delay,
+            pending: Vec::new(),
+            accepted: Vec::new(),
+        }
+    }
+
+    /// Get the acceptance delay.
+    #[must_use]
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+
+    /// Set the acceptance delay.
+    ///
+    /// The new delay also applies to the pending key presses.
+    pub fn set_delay(&mut self, delay: Duration) {
+        self.delay = delay;
+    }
+
+    /// Filter a key event which happened at time `time`.
+    ///
+    /// Pending presses whose delay has elapsed at `time` are accepted first.
+    /// Then a key press is reported as pending (or accepted right away if
+    /// the delay is zero), and a key release is accepted if the press was
+    /// accepted, or rejected along with the pending press otherwise.
+    pub fn filter(
+        &mut self,
+        key: Keycode,
+        direction: KeyDirection,
+        time: Duration,
+    ) -> Vec<FilterEvent> {
+        let mut events = self.poll(time);
+        match direction {
+            KeyDirection::Down => {
+                if self.accepted.contains(&key) || self.pending.iter().any(|&(k, _)| k == key) {
+                    // Key repeat or duplicated event.
+                    return events;
+                }
+                if self.delay.is_zero() {
+                    self.accepted.push(key);
+                    events.push(FilterEvent::Accepted(key, KeyDirection::Down));
+                } else {
+                    self.pending.push((key, time));
+                    events.push(FilterEvent::Pending(key));
+                }
+            }
+            KeyDirection::Up => {
+                if let Some(pos) = self.accepted.iter().position(|&k| k == key) {
+                    self.accepted.remove(pos);
+                    events.push(FilterEvent::Accepted(key, KeyDirection::Up));
+                } else if let Some(pos) = self.pending.iter().position(|&(k, _)| k == key) {
+                    self.pending.remove(pos);
+                    events.push(FilterEvent::Rejected(key, KeyDirection::Up));
+                }
+            }
+        }
+        events
+    }
+
+    /// Accept the pending key presses whose delay has elapsed at time `now`.
+    pub fn poll(&mut self, now: Duration) -> Vec<FilterEvent> {
+        let mut events = Vec::new();
+        let delay = self.delay;
+        let accepted = &mut self.accepted;
+        self.pending.retain(|&(key, time)| {
+            if now.saturating_sub(time) >= delay {
+                accepted.push(key);
+                events.push(FilterEvent::Accepted(key, KeyDirection::Down));
+                false
+            } else {
+                true
+            }
+        });
+        events
+    }
+
+    /// Get the time at which the next pending key press is to be accepted,
+    /// or `None` if no key press is pending.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.pending
+            .iter()
+            .map(|&(_, time)| time + self.delay)
+            .min()
+    }
+}
+
+#[test]
+fn slow_keys_accept_held_keys() {
+    let key = Keycode::new(38);
+    let mut slow_keys = SlowKeys::new(Duration::from_millis(300));
+    assert_eq!(
+        slow_keys.filter(key, KeyDirection::Down, Duration::from_millis(1000)),
+        [FilterEvent::Pending(key)]
+    );
+    assert_eq!(slow_keys.next_deadline(), Some(Duration::from_millis(1300)));
+    assert!(slow_keys.poll(Duration::from_millis(1299)).is_empty());
+    assert_eq!(
+        slow_keys.poll(Duration::from_millis(1300)),
+        [FilterEvent::Accepted(key, KeyDirection::Down)]
+    );
+    assert_eq!(
+        slow_keys.filter(key, KeyDirection::Up, Duration::from_millis(1500)),
+        [FilterEvent::Accepted(key, KeyDirection::Up)]
+    );
+    assert_eq!(slow_keys.next_deadline(), None);
+}
+
+#[test]
+fn slow_keys_reject_brief_presses() {
+    let key = Keycode::new(38);
+    let mut slow_keys = SlowKeys::new(Duration::from_millis(300));
+    slow_keys.filter(key, KeyDirection::Down, Duration::from_millis(1000));
+    assert_eq!(
+        slow_keys.filter(key, KeyDirection::Up, Duration::from_millis(1100)),
+        [FilterEvent::Rejected(key, KeyDirection::Up)]
+    );
+    assert!(slow_keys.poll(Duration::from_millis(2000)).is_empty());
+}
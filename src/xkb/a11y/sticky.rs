@@ -0,0 +1,432 @@
+use crate::xkb::{KeyDirection, Keycode, LedMask, ModMask, State, StateValues};
+
+/// Feedback events emitted by `StickyKeys`, e.g. to beep or to display the
+/// sticky modifiers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StickyKeysEvent {
+    /// Modifiers were latched by pressing and releasing a modifier key.
+    Latched(ModMask),
+    /// Latched modifiers were unlatched by pressing their key again.
+    Unlatched(ModMask),
+    /// Latched modifiers were locked by pressing their key again.
+    Locked(ModMask),
+    /// Locked modifiers were unlocked by pressing their key again.
+    Unlocked(ModMask),
+    /// Latched modifiers were cleared after being used by a key press.
+    Cleared(ModMask),
+    /// StickyKeys were enabled.
+    Enabled,
+    /// StickyKeys were disabled, either explicitly or by pressing two keys
+    /// at once.
+    Disabled,
+    /// LEDs of the state were turned on or off, e.g. the Caps Lock LED when
+    /// the Lock modifier is locked. This is reported for all the LED changes,
+    /// including those done by the keymap itself.
+    LedsChanged { on: LedMask, off: LedMask },
+}
+
+/// A modifier key being held.
+struct HeldKey {
+    key: Keycode,
+    mods: ModMask,
+    /// Whether another key was pressed while this one was held, in which
+    /// case it was used as a regular modifier and must not stick.
+    chorded: bool,
+}
+
+/// StickyKeys accessibility filter.
+///
+/// With StickyKeys, pressing and releasing a modifier key latches its
+/// modifiers until the next non-modifier key press, so that users who
+/// cannot hold several keys at once can still type shortcuts. Pressing the
+/// modifier key again either locks the modifiers (if latch-to-lock is
+/// enabled) or unlatches them; pressing it once more unlocks them.
+///
+/// The sticky modifiers are latched and locked in the underlying `State`
+/// through `State::update_mask()`, alongside the latches and locks done
+/// by the keymap itself (e.g. `ISO_Level3_Latch` or Caps Lock), which are
+/// left untouched. A modifier key held while another key is pressed acts
+/// as a regular modifier.
+///
+/// As with `State::update_key()`, the keysyms of a key should be retrieved
+/// from `state()` before feeding the key press to `update_key()`, so that
+/// they are affected by the sticky modifiers.
+pub struct StickyKeys {
+    state: State,
+    enabled: bool,
+    two_key_disable: bool,
+    latch_to_lock: bool,
+    latched: ModMask,
+    locked: ModMask,
+    held: Vec<HeldKey>,
+    pressed: Vec<Keycode>,
+}
+
+impl StickyKeys {
+    /// Wrap a keyboard state with StickyKeys.
+    ///
+    /// StickyKeys are enabled, latch-to-lock is enabled and two-key disable
+    /// is disabled.
+    #[must_use]
+    pub fn new(state: State) -> StickyKeys {
+        StickyKeys {
+            state,
+            enabled: true,
+            two_key_disable: false,
+            latch_to_lock: true,
+            latched: 0,
+            locked: 0,
+            held: Vec::new(),
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Get the underlying keyboard state object.
+    #[must_use]
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Stop filtering and get the underlying keyboard state object.
+    ///
+    /// The sticky modifiers remain latched or locked in the state.
+    #[must_use]
+    pub fn into_state(self) -> State {
+        self.state
+    }
+
+    /// Whether StickyKeys are enabled.
+    #[must_use]
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enable or disable StickyKeys.
+    ///
+    /// Disabling StickyKeys clears the sticky modifiers from the state.
+    ///
+    /// Returns the feedback events caused by the change.
+    pub fn set_enabled(&mut self, enabled: bool) -> Vec<StickyKeysEvent> {
+        if enabled == self.enabled {
+            return Vec::new();
+        }
+        if enabled {
+            self.enabled = true;
+            return vec![StickyKeysEvent::Enabled];
+        }
+        let leds = self.state.serialize_leds();
+        self.disable();
+        let mut events = vec![StickyKeysEvent::Disabled];
+        events.extend(self.leds_changed(leds));
+        events
+    }
+
+    /// Disable StickyKeys and clear the sticky modifiers from the state.
+    fn disable(&mut self) {
+        self.enabled = false;
+        self.held.clear();
+        self.set_sticky(0, 0);
+    }
+
+    /// Set whether pressing two keys at once disables StickyKeys.
+    ///
+    /// This lets users who can hold several keys at once turn StickyKeys
+    /// off by simply typing a shortcut the regular way.
+    pub fn set_two_key_disable(&mut self, two_key_disable: bool) {
+        self.two_key_disable = two_key_disable;
+    }
+
+    /// Set whether pressing a modifier key twice locks its modifiers.
+    ///
+    /// If disabled, pressing the modifier key of latched modifiers
+    /// unlatches them instead.
+    pub fn set_latch_to_lock(&mut self, latch_to_lock: bool) {
+        self.latch_to_lock = latch_to_lock;
+    }
+
+    /// Get the modifiers currently latched by StickyKeys.
+    #[must_use]
+    pub fn latched_mods(&self) -> ModMask {
+        self.latched
+    }
+
+    /// Get the modifiers currently locked by StickyKeys.
+    #[must_use]
+    pub fn locked_mods(&self) -> ModMask {
+        self.locked
+    }
+
+    /// Update the keyboard state to reflect a given key being pressed or
+    /// released, applying StickyKeys.
+    ///
+    /// Returns the feedback events caused by the key event.
+    pub fn update_key(&mut self, key: Keycode, direction: KeyDirection) -> Vec<StickyKeysEvent> {
+        let leds = self.state.serialize_leds();
+        let mut events = self.filter_key(key, direction);
+        events.extend(self.leds_changed(leds));
+        events
+    }
+
+    fn filter_key(&mut self, key: Keycode, direction: KeyDirection) -> Vec<StickyKeysEvent> {
+        let mut events = Vec::new();
+        match direction {
+            KeyDirection::Down => {
+                let others_pressed = !self.pressed.is_empty();
+                self.pressed.push(key);
+                let old = self.state.values();
+                self.state.update_key(key, KeyDirection::Down);
+                if !self.enabled {
+                    return events;
+                }
+                if self.two_key_disable && others_pressed {
+                    self.disable();
+                    events.push(StickyKeysEvent::Disabled);
+                    return events;
+                }
+                for held in &mut self.held {
+                    held.chorded = true;
+                }
+                let new = self.state.values();
+                let pressed = new.depressed_mods & !old.depressed_mods;
+                // Keys which latch or lock through the keymap, such as Caps
+                // Lock, are not made sticky again.
+                let mods = pressed & !self.keymap_sticky(&new);
+                if mods != 0 {
+                    self.held.push(HeldKey {
+                        key,
+                        mods,
+                        chorded: false,
+                    });
+                } else if pressed == 0 && self.latched != 0 && !Self::latches_or_locks(&old, &new) {
+                    // The latched modifiers were used by this key.
+                    events.push(StickyKeysEvent::Cleared(self.latched));
+                    self.set_sticky(0, self.locked);
+                }
+            }
+            KeyDirection::Up => {
+                if let Some(pos) = self.pressed.iter().position(|&k| k == key) {
+                    self.pressed.remove(pos);
+                }
+                self.state.update_key(key, KeyDirection::Up);
+                let Some(pos) = self.held.iter().position(|held| held.key == key) else {
+                    return events;
+                };
+                let held = self.held.remove(pos);
+                let mods = held.mods & !self.keymap_sticky(&self.state.values());
+                if held.chorded || !self.enabled || mods == 0 {
+                    return events;
+                }
+                let (latched, locked) = if self.locked & mods == mods {
+                    events.push(StickyKeysEvent::Unlocked(mods));
+                    (self.latched, self.locked & !mods)
+                } else if self.latched & mods == mods {
+                    if self.latch_to_lock {
+                        events.push(StickyKeysEvent::Locked(mods));
+                        (self.latched & !mods, self.locked | mods)
+                    } else {
+                        events.push(StickyKeysEvent::Unlatched(mods));
+                        (self.latched & !mods, self.locked)
+                    }
+                } else {
+                    events.push(StickyKeysEvent::Latched(mods));
+                    (self.latched | mods, self.locked & !mods)
+                };
+                self.set_sticky(latched, locked);
+            }
+        }
+        events
+    }
+
+    /// Get the event reporting the LEDs changed since they were `old`.
+    fn leds_changed(&self, old: LedMask) -> Option<StickyKeysEvent> {
+        let new = self.state.serialize_leds();
+        (new != old).then_some(StickyKeysEvent::LedsChanged {
+            on: new & !old,
+            off: old & !new,
+        })
+    }
+
+    /// Whether a key press latched or locked modifiers or layouts through
+    /// the keymap itself.
+    fn latches_or_locks(old: &StateValues, new: &StateValues) -> bool {
+        new.latched_mods & !old.latched_mods != 0
+            || new.locked_mods != old.locked_mods
+            || new.latched_layout != old.latched_layout
+            || new.locked_layout != old.locked_layout
+    }
+
+    /// Get the modifiers latched or locked by the keymap itself, such as by
+    /// Caps Lock, rather than by StickyKeys.
+    fn keymap_sticky(&self, values: &StateValues) -> ModMask {
+        (values.latched_mods & !self.latched) | (values.locked_mods & !self.locked)
+    }
+
+    /// Replace the sticky modifiers in the state, preserving the latches and
+    /// locks which do not come from StickyKeys.
+    fn set_sticky(&mut self, latched: ModMask, locked: ModMask) {
+        let mut snapshot = self.state.snapshot();
+        snapshot.latched_mods = (snapshot.latched_mods & !self.latched) | latched;
+        snapshot.locked_mods = (snapshot.locked_mods & !self.locked) | locked;
+        self.latched = latched;
+        self.locked = locked;
+        self.state.restore(&snapshot);
+    }
+}
+
+#[cfg(test)]
+fn sticky_keys() -> StickyKeys {
+    use crate::xkb::{Context, Keymap, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    StickyKeys::new(State::new(&keymap))
+}
+
+#[cfg(test)]
+fn tap(sticky: &mut StickyKeys, name: &str) -> Vec<StickyKeysEvent> {
+    let key = sticky.state().get_keymap().key_by_name(name).unwrap();
+    let mut events = sticky.update_key(key, KeyDirection::Down);
+    events.extend(sticky.update_key(key, KeyDirection::Up));
+    events
+}
+
+#[test]
+fn sticky_keys_latch_and_clear() {
+    use crate::xkb::{MOD_NAME_SHIFT, STATE_MODS_EFFECTIVE, STATE_MODS_LATCHED};
+
+    let mut sticky = sticky_keys();
+    let shift = 1 << sticky.state().get_keymap().mod_get_index(MOD_NAME_SHIFT);
+    let a = sticky.state().get_keymap().key_by_name("AC01").unwrap();
+
+    assert_eq!(tap(&mut sticky, "LFSH"), [StickyKeysEvent::Latched(shift)]);
+    assert_eq!(sticky.latched_mods(), shift);
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LATCHED), shift);
+
+    // The latch applies to the next key, then is cleared.
+    assert_eq!(sticky.state().key_get_utf8(a), "A");
+    assert_eq!(
+        sticky.update_key(a, KeyDirection::Down),
+        [StickyKeysEvent::Cleared(shift)]
+    );
+    sticky.update_key(a, KeyDirection::Up);
+    assert_eq!(sticky.latched_mods(), 0);
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_EFFECTIVE), 0);
+    assert_eq!(sticky.state().key_get_utf8(a), "a");
+
+    // A modifier held while another key is pressed does not stick.
+    let lfsh = sticky.state().get_keymap().key_by_name("LFSH").unwrap();
+    sticky.update_key(lfsh, KeyDirection::Down);
+    assert!(tap(&mut sticky, "AC01").is_empty());
+    assert!(sticky.update_key(lfsh, KeyDirection::Up).is_empty());
+    assert_eq!(sticky.latched_mods(), 0);
+
+    // Without latch-to-lock, a second tap unlatches.
+    sticky.set_latch_to_lock(false);
+    tap(&mut sticky, "LFSH");
+    assert_eq!(
+        tap(&mut sticky, "LFSH"),
+        [StickyKeysEvent::Unlatched(shift)]
+    );
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LATCHED), 0);
+}
+
+#[test]
+fn sticky_keys_latch_to_lock() {
+    use crate::xkb::{MOD_NAME_SHIFT, STATE_MODS_LATCHED, STATE_MODS_LOCKED};
+
+    let mut sticky = sticky_keys();
+    let keymap = sticky.state().get_keymap();
+    let shift = 1 << keymap.mod_get_index(MOD_NAME_SHIFT);
+    let led = 1 << keymap.led_get_index("Shift Lock");
+
+    tap(&mut sticky, "LFSH");
+    assert_eq!(
+        tap(&mut sticky, "LFSH"),
+        [
+            StickyKeysEvent::Locked(shift),
+            StickyKeysEvent::LedsChanged { on: led, off: 0 }
+        ]
+    );
+    assert_eq!((sticky.latched_mods(), sticky.locked_mods()), (0, shift));
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LATCHED), 0);
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LOCKED), shift);
+
+    // The lock survives key presses.
+    assert!(tap(&mut sticky, "AC01").is_empty());
+    assert_eq!(sticky.locked_mods(), shift);
+    assert_eq!(
+        tap(&mut sticky, "LFSH"),
+        [
+            StickyKeysEvent::Unlocked(shift),
+            StickyKeysEvent::LedsChanged { on: 0, off: led }
+        ]
+    );
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LOCKED), 0);
+
+    // Disabling StickyKeys clears the locks.
+    tap(&mut sticky, "LFSH");
+    tap(&mut sticky, "LFSH");
+    assert_eq!(
+        sticky.set_enabled(false),
+        [
+            StickyKeysEvent::Disabled,
+            StickyKeysEvent::LedsChanged { on: 0, off: led }
+        ]
+    );
+    assert_eq!(sticky.locked_mods(), 0);
+    assert!(sticky.set_enabled(false).is_empty());
+    assert_eq!(tap(&mut sticky, "LFSH"), []);
+}
+
+#[test]
+fn sticky_keys_keymap_locks() {
+    use crate::xkb::{STATE_MODS_LATCHED, STATE_MODS_LOCKED};
+
+    let mut sticky = sticky_keys();
+    let a = sticky.state().get_keymap().key_by_name("AC01").unwrap();
+
+    // Caps Lock locks through the keymap, so StickyKeys leaves it alone.
+    assert!(tap(&mut sticky, "CAPS")
+        .iter()
+        .all(|event| matches!(event, StickyKeysEvent::LedsChanged { .. })));
+    assert_eq!(sticky.locked_mods(), 0);
+    assert_eq!(sticky.state().key_get_utf8(a), "A");
+    tap(&mut sticky, "CAPS");
+    assert_eq!(sticky.latched_mods(), 0);
+    assert_eq!(sticky.locked_mods(), 0);
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LATCHED), 0);
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_LOCKED), 0);
+    assert_eq!(sticky.state().key_get_utf8(a), "a");
+}
+
+#[test]
+fn sticky_keys_two_key_disable() {
+    use crate::xkb::{MOD_NAME_CTRL, STATE_MODS_DEPRESSED};
+
+    let mut sticky = sticky_keys();
+    let keymap = sticky.state().get_keymap();
+    let ctrl = 1 << keymap.mod_get_index(MOD_NAME_CTRL);
+    let lctl = keymap.key_by_name("LCTL").unwrap();
+    sticky.set_two_key_disable(true);
+    tap(&mut sticky, "LCTL");
+    assert_eq!(sticky.latched_mods(), ctrl);
+
+    sticky.update_key(lctl, KeyDirection::Down);
+    assert_eq!(tap(&mut sticky, "AC01"), [StickyKeysEvent::Disabled]);
+    assert!(!sticky.enabled());
+    assert_eq!(sticky.latched_mods(), 0);
+    // The held modifier still acts as a regular modifier.
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_DEPRESSED), ctrl);
+    assert!(sticky.update_key(lctl, KeyDirection::Up).is_empty());
+    assert_eq!(sticky.state().serialize_mods(STATE_MODS_DEPRESSED), 0);
+    assert_eq!(sticky.set_enabled(true), [StickyKeysEvent::Enabled]);
+}
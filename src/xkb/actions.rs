@@ -0,0 +1,346 @@
+use super::introspect::{group_index, mod_mask};
+use super::text::{Expr, ExprKind, UnaryOp, VarDef};
+use super::{Keymap, LayoutIndex, ModMask};
+
+/// Which of locking and unlocking a locking action does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum LockAffect {
+    /// Lock on press if not locked, unlock on release if it was locked.
+    #[default]
+    Both,
+    /// Only lock.
+    Lock,
+    /// Only unlock.
+    Unlock,
+    /// Neither lock nor unlock.
+    Neither,
+}
+
+/// Group of a group action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GroupValue {
+    /// An absolute layout index.
+    Absolute(LayoutIndex),
+    /// An offset to the current layout.
+    Relative(i32),
+}
+
+/// An action bound to a key, which changes the keyboard state or has a side
+/// effect when the key is pressed.
+///
+/// Modifier masks are masks of modifier indices, as in `KeyType`. Actions
+/// using the modifiers of the key (`modifiers=modMapMods`) are resolved to
+/// the modifiers the key is mapped to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    /// `NoAction()`.
+    NoAction,
+    /// `SetMods()`: set modifiers while the key is held.
+    SetMods {
+        modifiers: ModMask,
+        clear_locks: bool,
+    },
+    /// `LatchMods()`: set modifiers until the next key press.
+    LatchMods {
+        modifiers: ModMask,
+        clear_locks: bool,
+        latch_to_lock: bool,
+    },
+    /// `LockMods()`: toggle locked modifiers.
+    LockMods {
+        modifiers: ModMask,
+        affect: LockAffect,
+    },
+    /// `SetGroup()`: set the layout while the key is held.
+    SetGroup {
+        group: GroupValue,
+        clear_locks: bool,
+    },
+    /// `LatchGroup()`: set the layout until the next key press.
+    LatchGroup {
+        group: GroupValue,
+        clear_locks: bool,
+        latch_to_lock: bool,
+    },
+    /// `LockGroup()`: set the locked layout.
+    LockGroup { group: GroupValue },
+    /// `MovePtr()`: move the pointer. Relative coordinates are offsets.
+    MovePtr {
+        x: i32,
+        y: i32,
+        absolute_x: bool,
+        absolute_y: bool,
+        accelerate: bool,
+    },
+    /// `PtrBtn()`: press a pointer button, or the default button if `None`.
+    PtrBtn { button: Option<u32>, count: u32 },
+    /// `LockPtrBtn()`: lock a pointer button, or the default button if
+    /// `None`.
+    LockPtrBtn {
+        button: Option<u32>,
+        affect: LockAffect,
+    },
+    /// `SetPtrDflt()`: change the default pointer button.
+    SetPtrDflt { button: i32, absolute: bool },
+    /// `SetControls()`: enable controls while the key is held, e.g.
+    /// `MouseKeys`.
+    SetControls { controls: Vec<String> },
+    /// `LockControls()`: toggle controls.
+    LockControls {
+        controls: Vec<String>,
+        affect: LockAffect,
+    },
+    /// `SwitchScreen()`: switch to another screen, e.g. a virtual terminal.
+    SwitchScreen {
+        screen: i32,
+        absolute: bool,
+        same_server: bool,
+    },
+    /// `Terminate()`: terminate the server.
+    Terminate,
+    /// `RedirectKey()`: act as another key.
+    RedirectKey {
+        key: String,
+        modifiers: ModMask,
+        clear_modifiers: ModMask,
+    },
+    /// `Private()`: an action with raw data.
+    Private { action_type: u8, data: [u8; 7] },
+    /// Any other action, with its name and its text.
+    Other { name: String, text: String },
+}
+
+/// Get the value of a named argument of an action.
+fn arg<'a>(args: &'a [VarDef], name: &str) -> Option<&'a Expr> {
+    args.iter()
+        .rev()
+        .find(|arg| arg.name.is_some() && arg.field().is_some_and(|f| f.eq_ignore_ascii_case(name)))
+        .map(|arg| &arg.value)
+}
+
+/// Get a boolean argument of an action, either as a flag (`same`, `!same`)
+/// or as a value (`same=false`).
+fn flag(args: &[VarDef], name: &str) -> Option<bool> {
+    for arg in args.iter().rev() {
+        let (field, negated) = match (&arg.name, &arg.value.kind) {
+            (None, ExprKind::Ident(field)) => (field.as_str(), false),
+            (None, ExprKind::Unary(UnaryOp::Not | UnaryOp::Invert, expr)) => {
+                match expr.as_ident() {
+                    Some(field) => (field, true),
+                    None => continue,
+                }
+            }
+            (Some(_), _) => match arg.field() {
+                Some(field) if field.eq_ignore_ascii_case(name) => {
+                    return arg.value.as_ident().map(|value| {
+                        ["true", "yes", "on"]
+                            .iter()
+                            .any(|v| value.eq_ignore_ascii_case(v))
+                    });
+                }
+                _ => continue,
+            },
+            _ => continue,
+        };
+        if field.eq_ignore_ascii_case(name) {
+            return Some(!negated);
+        }
+    }
+    None
+}
+
+/// Get a signed value, which is relative if it has an explicit sign.
+fn signed(expr: Option<&Expr>) -> (i32, bool) {
+    let Some(expr) = expr else {
+        return (0, false);
+    };
+    let value = expr
+        .as_integer()
+        .and_then(|value| i32::try_from(value).ok())
+        .unwrap_or(0);
+    let relative = matches!(
+        expr.kind,
+        ExprKind::Unary(UnaryOp::Negate | UnaryOp::Plus, _)
+    );
+    (value, relative)
+}
+
+fn group(expr: Option<&Expr>) -> GroupValue {
+    match signed(expr) {
+        (value, true) => GroupValue::Relative(value),
+        _ => GroupValue::Absolute(expr.and_then(group_index).unwrap_or(0)),
+    }
+}
+
+fn affect(args: &[VarDef]) -> LockAffect {
+    match arg(args, "affect").and_then(Expr::as_ident) {
+        Some(affect) if affect.eq_ignore_ascii_case("lock") => LockAffect::Lock,
+        Some(affect) if affect.eq_ignore_ascii_case("unlock") => LockAffect::Unlock,
+        Some(affect) if affect.eq_ignore_ascii_case("neither") => LockAffect::Neither,
+        _ => LockAffect::Both,
+    }
+}
+
+fn button(expr: Option<&Expr>) -> Option<u32> {
+    expr.and_then(Expr::as_integer)
+        .and_then(|button| u32::try_from(button).ok())
+        .filter(|&button| button != 0)
+}
+
+/// Get the names of a `+`-separated list of controls.
+fn controls(expr: Option<&Expr>, names: &mut Vec<String>) {
+    match expr.map(|expr| &expr.kind) {
+        Some(ExprKind::Ident(name)) if !name.eq_ignore_ascii_case("none") => {
+            names.push(name.clone());
+        }
+        Some(ExprKind::Binary(_, lhs, rhs)) => {
+            controls(Some(lhs), names);
+            controls(Some(rhs), names);
+        }
+        _ => {}
+    }
+}
+
+impl Action {
+    /// Convert an action expression of the keymap text.
+    ///
+    /// `modmap` is the mask of the modifiers of the key, to resolve
+    /// `modMapMods`.
+    pub(crate) fn from_expr(keymap: &Keymap, expr: &Expr, modmap: ModMask) -> Action {
+        let ExprKind::Call { name, args } = &expr.kind else {
+            return Action::Other {
+                name: String::new(),
+                text: expr.to_string(),
+            };
+        };
+        let mods = |field: &str, alias: &str| {
+            arg(args, field)
+                .or(arg(args, alias))
+                .map_or(0, |mods| match mods.as_ident() {
+                    Some(mods) if mods.eq_ignore_ascii_case("modMapMods") => modmap,
+                    _ => mod_mask(keymap, mods),
+                })
+        };
+        let modifiers = mods("modifiers", "mods");
+        let clear_locks = flag(args, "clearLocks").unwrap_or(false);
+        let latch_to_lock = flag(args, "latchToLock").unwrap_or(false);
+        match name.to_ascii_lowercase().as_str() {
+            "noaction" => Action::NoAction,
+            "setmods" => Action::SetMods {
+                modifiers,
+                clear_locks,
+            },
+            "latchmods" => Action::LatchMods {
+                modifiers,
+                clear_locks,
+                latch_to_lock,
+            },
+            "lockmods" => Action::LockMods {
+                modifiers,
+                affect: affect(args),
+            },
+            "setgroup" => Action::SetGroup {
+                group: group(arg(args, "group")),
+                clear_locks,
+            },
+            "latchgroup" => Action::LatchGroup {
+                group: group(arg(args, "group")),
+                clear_locks,
+                latch_to_lock,
+            },
+            "lockgroup" => Action::LockGroup {
+                group: group(arg(args, "group")),
+            },
+            "moveptr" | "movepointer" => {
+                let (x, relative_x) = signed(arg(args, "x"));
+                let (y, relative_y) = signed(arg(args, "y"));
+                Action::MovePtr {
+                    x,
+                    y,
+                    absolute_x: !relative_x,
+                    absolute_y: !relative_y,
+                    accelerate: flag(args, "accel")
+                        .or(flag(args, "accelerate"))
+                        .unwrap_or(true),
+                }
+            }
+            "ptrbtn" | "pointerbutton" => Action::PtrBtn {
+                button: button(arg(args, "button")),
+                count: arg(args, "count")
+                    .and_then(Expr::as_integer)
+                    .and_then(|count| u32::try_from(count).ok())
+                    .unwrap_or(0),
+            },
+            "lockptrbtn" | "lockpointerbutton" | "lockptrbutton" | "lockpointerbtn" => {
+                Action::LockPtrBtn {
+                    button: button(arg(args, "button")),
+                    affect: affect(args),
+                }
+            }
+            "setptrdflt" | "setpointerdefault" => {
+                let (button, relative) = signed(arg(args, "button"));
+                Action::SetPtrDflt {
+                    button,
+                    absolute: !relative,
+                }
+            }
+            "setcontrols" | "lockcontrols" => {
+                let mut names = Vec::new();
+                controls(arg(args, "controls").or(arg(args, "ctrls")), &mut names);
+                if name.eq_ignore_ascii_case("setcontrols") {
+                    Action::SetControls { controls: names }
+                } else {
+                    Action::LockControls {
+                        controls: names,
+                        affect: affect(args),
+                    }
+                }
+            }
+            "switchscreen" => {
+                let (screen, relative) = signed(arg(args, "screen"));
+                Action::SwitchScreen {
+                    screen,
+                    absolute: !relative,
+                    same_server: flag(args, "same")
+                        .or(flag(args, "sameServer"))
+                        .unwrap_or(true),
+                }
+            }
+            "terminate" | "terminateserver" => Action::Terminate,
+            "redirectkey" | "redirect" => Action::RedirectKey {
+                key: match arg(args, "key").map(|key| &key.kind) {
+                    Some(ExprKind::KeyName(key)) => key.clone(),
+                    _ => String::new(),
+                },
+                modifiers,
+                clear_modifiers: mods("clearMods", "clearModifiers"),
+            },
+            "private" => {
+                let byte = |expr: Option<&Expr>| {
+                    expr.and_then(Expr::as_integer)
+                        .and_then(|value| u8::try_from(value).ok())
+                        .unwrap_or(0)
+                };
+                let mut data = [0; 7];
+                for arg in args {
+                    let index = arg.index().and_then(Expr::as_integer);
+                    if arg.field().is_some_and(|f| f.eq_ignore_ascii_case("data")) {
+                        if let Some(slot) =
+                            index.and_then(|i| data.get_mut(usize::try_from(i).ok()?))
+                        {
+                            *slot = byte(Some(&arg.value));
+                        }
+                    }
+                }
+                Action::Private {
+                    action_type: byte(arg(args, "type")),
+                    data,
+                }
+            }
+            _ => Action::Other {
+                name: name.clone(),
+                text: expr.to_string(),
+            },
+        }
+    }
+}
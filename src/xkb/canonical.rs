@@ -0,0 +1,430 @@
+//! Canonical formatting of keymaps.
+//!
+//! The text written by `Keymap::get_as_string()` follows the internal order
+//! of libxkbcommon, and spells out values which are the default anyway. This
+//! module writes keymaps in a canonical form instead, meant to be checked
+//! into version control and reviewed as diffs: sections, keycodes, types,
+//! indicators and keys are sorted, the fields of types and keys are written
+//! in a fixed order, and whitespace is normalized by the `Display`
+//! implementation of `xkb::text`.
+//!
+//! Statements are only reordered where their order has no meaning: the
+//! interprets of the compat section keep their order, as the first matching
+//! one applies, and no statement is moved across a variable definition or an
+//! include, which affect the statements after them.
+
+use super::introspect::{group_index, implicit_key_type, level_index};
+use super::text::{
+    self, Expr, ExprKind, KeymapFile, ParseError, Section, SectionKind, StatementKind, VarDef,
+};
+use super::{Keymap, LayoutIndex};
+use std::collections::{HashMap, HashSet};
+
+pub type CanonicalFlags = u32;
+
+pub const CANONICAL_NO_FLAGS: u32 = 0;
+/// Omit the fields set to their default value, such as
+/// `interpret.repeat= False;`, `preserve[Lock]= None;` or a key type which
+/// libxkbcommon would assign to the key anyway.
+pub const CANONICAL_OMIT_DEFAULTS: u32 = 1 << 0;
+
+/// Default values of the interpret fields, in lowercase.
+const INTERPRET_DEFAULTS: [(&str, &[&str]); 3] = [
+    ("repeat", &["false", "no", "off"]),
+    ("locking", &["false", "no", "off"]),
+    ("usemodmapmods", &["anylevel", "any"]),
+];
+
+/// Order of the real modifiers in `modifier_map` statements.
+const REAL_MODS: [&str; 8] = [
+    "shift", "lock", "control", "mod1", "mod2", "mod3", "mod4", "mod5",
+];
+
+fn is_ident(expr: &Expr, values: &[&str]) -> bool {
+    expr.as_ident()
+        .is_some_and(|ident| values.iter().any(|v| ident.eq_ignore_ascii_case(v)))
+}
+
+fn section_rank(kind: SectionKind) -> u8 {
+    match kind {
+        SectionKind::Keycodes => 0,
+        SectionKind::Types => 1,
+        SectionKind::Compat => 2,
+        SectionKind::Symbols => 3,
+        SectionKind::Geometry => 4,
+    }
+}
+
+/// Sort key of statements and fields: rank, number, then name.
+type SortKey = (u8, i64, String);
+
+fn statement_key(kind: &StatementKind, keycodes: &HashMap<String, i64>) -> SortKey {
+    let keycode = |name: &str| keycodes.get(name).copied().unwrap_or(i64::MAX);
+    match kind {
+        StatementKind::VirtualModifiers(_) => (0, 0, String::new()),
+        StatementKind::Keycode { name, value } => {
+            (1, value.as_integer().unwrap_or(i64::MAX), name.clone())
+        }
+        StatementKind::IndicatorName { index, .. } => (2, i64::from(*index), String::new()),
+        StatementKind::Alias { alias, .. } => (3, 0, alias.clone()),
+        StatementKind::KeyType { name, .. } => (1, 0, name.clone()),
+        StatementKind::Interpret { .. } => (1, 0, String::new()),
+        StatementKind::IndicatorMap { name, .. } => (2, 0, name.clone()),
+        StatementKind::GroupCompat { group, .. } => (3, i64::from(*group), String::new()),
+        StatementKind::Key { name, .. } => (1, keycode(name), name.clone()),
+        StatementKind::ModifierMap { modifier, .. } => {
+            let lower = modifier.to_ascii_lowercase();
+            match REAL_MODS.iter().position(|&m| m == lower) {
+                Some(idx) => (2, idx as i64, String::new()),
+                None => (2, REAL_MODS.len() as i64, modifier.clone()),
+            }
+        }
+        StatementKind::Include { .. } | StatementKind::Var(_) => (4, 0, String::new()),
+    }
+}
+
+fn field(var: &VarDef) -> String {
+    var.field().unwrap_or_default().to_ascii_lowercase()
+}
+
+fn index_text(var: &VarDef) -> String {
+    var.index().map(ToString::to_string).unwrap_or_default()
+}
+
+fn group_key(var: &VarDef) -> i64 {
+    var.index()
+        .map_or(-1, |index| group_index(index).map_or(i64::MAX, i64::from))
+}
+
+fn type_field_key(var: &VarDef) -> SortKey {
+    match field(var).as_str() {
+        "modifiers" => (0, 0, String::new()),
+        "map" => (
+            1,
+            level_index(&var.value).map_or(i64::MAX, i64::from),
+            index_text(var),
+        ),
+        "preserve" => (2, 0, index_text(var)),
+        "level_name" | "levelname" => (
+            3,
+            var.index()
+                .and_then(level_index)
+                .map_or(i64::MAX, i64::from),
+            String::new(),
+        ),
+        _ => (4, 0, String::new()),
+    }
+}
+
+fn key_field_key(var: &VarDef) -> SortKey {
+    match (var.name.is_some(), field(var).as_str()) {
+        (true, "type") => (0, group_key(var), String::new()),
+        (true, "repeat" | "repeats") => (1, 0, String::new()),
+        (true, "vmods" | "virtualmods" | "virtualmodifiers") => (2, 0, String::new()),
+        (false, _) | (true, "symbols") => (3, 2 * group_key(var), String::new()),
+        (true, "actions") => (3, 2 * group_key(var) + 1, String::new()),
+        _ => (4, 0, String::new()),
+    }
+}
+
+/// Whether the type of a key statement is the automatic type of the groups
+/// it applies to.
+fn is_implicit_type(var: &VarDef, body: &[VarDef]) -> bool {
+    let Some(name) = var.value.as_string() else {
+        return false;
+    };
+    let groups: Vec<LayoutIndex> = match var.index() {
+        Some(index) => group_index(index).into_iter().collect(),
+        None => body
+            .iter()
+            .filter(|var| var.name.is_none() || field(var) == "symbols")
+            .filter_map(|var| var.index().map_or(Some(0), group_index))
+            .collect(),
+    };
+    !groups.is_empty()
+        && groups
+            .iter()
+            .all(|&group| implicit_key_type(body, group) == Some(name))
+}
+
+/// Give a group index to the unnamed symbols and actions of a key body:
+/// each list applies to the first group not defined yet.
+pub(crate) fn name_groups(body: &mut [VarDef]) {
+    let mut defined: HashSet<(bool, LayoutIndex)> = body
+        .iter()
+        .filter_map(|var| Some((field(var) == "actions", group_index(var.index()?)?)))
+        .collect();
+    for var in body.iter_mut().filter(|var| var.name.is_none()) {
+        let ExprKind::List(items) = &var.value.kind else {
+            continue;
+        };
+        let is_action = items.iter().any(|item| match &item.kind {
+            ExprKind::Braced(items) => {
+                matches!(items.first(), Some(item) if matches!(item.kind, ExprKind::Call { .. }))
+            }
+            kind => matches!(kind, ExprKind::Call { .. }),
+        });
+        let group = (0..)
+            .find(|&group| !defined.contains(&(is_action, group)))
+            .unwrap_or_default();
+        defined.insert((is_action, group));
+        let span = var.value.span;
+        let index = Expr {
+            kind: ExprKind::Ident(format!("Group{}", group + 1)),
+            span,
+        };
+        var.name = Some(Expr {
+            kind: ExprKind::ArrayRef {
+                elem: None,
+                field: if is_action { "actions" } else { "symbols" }.to_owned(),
+                index: Box::new(index),
+            },
+            span,
+        });
+    }
+}
+
+fn omit_defaults(section: &mut Section) {
+    let mut overridden = HashSet::new();
+    let is_default = |field: &str, value: &Expr, overridden: &HashSet<String>| {
+        !overridden.contains(field)
+            && INTERPRET_DEFAULTS
+                .iter()
+                .any(|&(f, values)| f == field && is_ident(value, values))
+    };
+    section
+        .statements
+        .retain_mut(|statement| match &mut statement.kind {
+            StatementKind::Var(var) => {
+                let elem = match var.name.as_ref().map(|name| &name.kind) {
+                    Some(ExprKind::FieldRef { elem, .. }) => elem.as_str(),
+                    _ => return true,
+                };
+                if !elem.eq_ignore_ascii_case("interpret") {
+                    return true;
+                }
+                let field = field(var);
+                let builtin = INTERPRET_DEFAULTS
+                    .iter()
+                    .any(|&(f, values)| f == field && is_ident(&var.value, values));
+                if is_default(&field, &var.value, &overridden) {
+                    return false;
+                }
+                if builtin {
+                    overridden.remove(&field);
+                } else {
+                    overridden.insert(field);
+                }
+                true
+            }
+            StatementKind::Interpret { body, .. } => {
+                body.retain(|var| !is_default(&field(var), &var.value, &overridden));
+                true
+            }
+            StatementKind::KeyType { body, .. } => {
+                // `map[...]= 1` entries are not defaults: they show in
+                // `Keymap::key_get_mods_for_level()`.
+                body.retain(|var| field(var) != "preserve" || !is_ident(&var.value, &["none"]));
+                true
+            }
+            StatementKind::Key { body, .. } => {
+                let implicit: Vec<bool> = body
+                    .iter()
+                    .map(|var| field(var) == "type" && is_implicit_type(var, body))
+                    .collect();
+                let mut implicit = implicit.into_iter();
+                body.retain(|_| !implicit.next().unwrap_or(false));
+                true
+            }
+            _ => true,
+        });
+}
+
+/// Rewrite a keymap syntax tree in canonical form.
+///
+/// The tree is expected to be a complete keymap as written by
+/// `Keymap::get_as_string()`; keys are sorted by the keycodes defined in the
+/// tree. The spans of the tree are left unchanged, and no longer match the
+/// formatted text.
+pub fn canonicalize(file: &mut KeymapFile, flags: CanonicalFlags) {
+    file.sections
+        .sort_by_key(|section| section_rank(section.kind));
+    let mut keycodes = HashMap::new();
+    let statements = file
+        .sections
+        .iter()
+        .filter(|section| section.kind == SectionKind::Keycodes)
+        .flat_map(|section| &section.statements);
+    for statement in statements.clone() {
+        if let StatementKind::Keycode { name, value } = &statement.kind {
+            if let Some(value) = value.as_integer() {
+                keycodes.insert(name.clone(), value);
+            }
+        }
+    }
+    for statement in statements {
+        if let StatementKind::Alias { alias, real } = &statement.kind {
+            if let Some(&value) = keycodes.get(real) {
+                keycodes.entry(alias.clone()).or_insert(value);
+            }
+        }
+    }
+    for section in &mut file.sections {
+        for statement in &mut section.statements {
+            if let StatementKind::Key { body, .. } = &mut statement.kind {
+                name_groups(body);
+            }
+        }
+        if flags & CANONICAL_OMIT_DEFAULTS != 0 {
+            omit_defaults(section);
+        }
+        for statement in &mut section.statements {
+            match &mut statement.kind {
+                StatementKind::KeyType { body, .. } => body.sort_by_cached_key(type_field_key),
+                StatementKind::Key { body, .. } => {
+                    body.sort_by_cached_key(key_field_key);
+                    // Write the symbols of a single group as `{ [ a, A ] }`.
+                    if let [var] = body.as_mut_slice() {
+                        if field(var) == "symbols" && group_key(var) == 0 {
+                            var.name = None;
+                        }
+                    }
+                }
+                StatementKind::ModifierMap { keys, .. } => {
+                    keys.sort_by_cached_key(|key| match &key.kind {
+                        ExprKind::KeyName(name) => (
+                            keycodes.get(name).copied().unwrap_or(i64::MAX),
+                            name.clone(),
+                        ),
+                        _ => (i64::MAX, key.to_string()),
+                    })
+                }
+                _ => {}
+            }
+        }
+        let separators = |kind: &StatementKind| {
+            matches!(kind, StatementKind::Var(_) | StatementKind::Include { .. })
+        };
+        for run in section
+            .statements
+            .split_mut(|statement| separators(&statement.kind))
+        {
+            run.sort_by_cached_key(|statement| statement_key(&statement.kind, &keycodes));
+        }
+    }
+}
+
+impl Keymap {
+    /// Get the keymap as text in canonical form.
+    ///
+    /// Two keymaps with the same content are written identically, which
+    /// makes the text suitable for version control: see `xkb::canonical`.
+    ///
+    /// # Errors
+    /// Returns an error if the text of the keymap cannot be parsed.
+    pub fn get_as_canonical_string(&self, flags: CanonicalFlags) -> Result<String, ParseError> {
+        let mut file = text::parse_keymap(self)?;
+        canonicalize(&mut file, flags);
+        Ok(file.to_string())
+    }
+}
+
+#[test]
+fn canonical_keymap() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS, KEYMAP_FORMAT_TEXT_V1};
+
+    let context = Context::new(0);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        Some("terminate:ctrl_alt_bksp".into()),
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let text = keymap
+        .get_as_canonical_string(CANONICAL_OMIT_DEFAULTS)
+        .unwrap();
+    assert!(!text.contains("interpret.repeat"));
+    let reparsed = Keymap::new_from_string(
+        &context,
+        text.clone(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    assert_eq!(
+        reparsed
+            .get_as_canonical_string(CANONICAL_OMIT_DEFAULTS)
+            .unwrap(),
+        text
+    );
+
+    // The same keymap, written in another order and format.
+    let canonical = |text: &str| {
+        Keymap::new_from_string(
+            &context,
+            text.into(),
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+        .get_as_canonical_string(CANONICAL_OMIT_DEFAULTS)
+        .unwrap()
+    };
+    let first = canonical(
+        r#"xkb_keymap {
+            xkb_keycodes { <AC01> = 38; <AC02> = 39; <LFSH> = 50; };
+            xkb_types {
+                type "ONE_LEVEL" { modifiers = None; level_name[Level1] = "Any"; };
+                type "TWO_LEVEL" {
+                    modifiers = Shift;
+                    map[Shift] = Level2;
+                    level_name[Level1] = "Base";
+                    level_name[Level2] = "Shift";
+                };
+                type "ALPHABETIC" {
+                    modifiers = Shift + Lock;
+                    map[Shift] = Level2;
+                    map[Lock] = Level2;
+                    level_name[Level1] = "Base";
+                    level_name[Level2] = "Caps";
+                };
+            };
+            xkb_compat { include "complete" };
+            xkb_symbols {
+                key <AC01> { [ a, A ] };
+                key <AC02> { type = "TWO_LEVEL", symbols[Group1] = [ s, S ] };
+                key <LFSH> { [ Shift_L ] };
+                modifier_map Shift { <LFSH> };
+            };
+        };"#,
+    );
+    let second = canonical(
+        r#"xkb_keymap{xkb_compat{include "complete"};
+        xkb_symbols {
+            modifier_map Shift{<LFSH>};
+            key <LFSH> {[Shift_L]};
+            key <AC02> {symbols[Group1]=[s,S], type="TWO_LEVEL"};
+            key <AC01> {[a,A]};
+        };
+        xkb_types {
+            type "ALPHABETIC" {
+                modifiers=Shift+Lock;
+                map[Lock]=Level2; map[Shift]=Level2;
+                level_name[Level2]="Caps"; level_name[Level1]="Base";
+            };
+            type "TWO_LEVEL" {
+                modifiers=Shift;
+                level_name[Level2]="Shift"; level_name[Level1]="Base";
+                map[Shift]=Level2;
+            };
+            type "ONE_LEVEL" {level_name[Level1]="Any"; modifiers=None;};
+        };
+        xkb_keycodes {<LFSH>=50; <AC02>=39; <AC01>=38;};
+        };"#,
+    );
+    assert_eq!(first, second);
+}
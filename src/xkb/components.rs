@@ -0,0 +1,199 @@
+//! Keymaps described by KcCGST component names.
+//!
+//! Besides rules, models, layouts, variants and options (RMLVO), a keymap
+//! can be described by the names of its keycodes, types, compat and symbols
+//! components (KcCGST), as shown by `setxkbmap -print`. Each component is
+//! an include statement resolved through the include paths of the context.
+
+use super::{Context, Keymap, KeymapCompileFlags, KEYMAP_FORMAT_TEXT_V1};
+use std::error::Error;
+use std::fmt;
+
+/// The component names of a keymap, e.g. `evdev+aliases(qwerty)` for the
+/// keycodes and `pc+us+de:2+inet(evdev)` for the symbols.
+///
+/// An empty component gives an empty section.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Components {
+    pub keycodes: String,
+    pub types: String,
+    pub compat: String,
+    pub symbols: String,
+}
+
+/// A component of a keymap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ComponentKind {
+    Keycodes,
+    Types,
+    Compat,
+    Symbols,
+}
+
+impl ComponentKind {
+    const ALL: [ComponentKind; 4] = [
+        ComponentKind::Keycodes,
+        ComponentKind::Types,
+        ComponentKind::Compat,
+        ComponentKind::Symbols,
+    ];
+
+    fn keyword(self) -> &'static str {
+        match self {
+            ComponentKind::Keycodes => "xkb_keycodes",
+            ComponentKind::Types => "xkb_types",
+            ComponentKind::Compat => "xkb_compatibility",
+            ComponentKind::Symbols => "xkb_symbols",
+        }
+    }
+}
+
+impl fmt::Display for ComponentKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ComponentKind::Keycodes => "keycodes",
+            ComponentKind::Types => "types",
+            ComponentKind::Compat => "compat",
+            ComponentKind::Symbols => "symbols",
+        })
+    }
+}
+
+/// Error of `Keymap::new_from_components()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentError {
+    /// The failing component, or `None` if each component compiles alone
+    /// but not together with the others.
+    pub component: Option<ComponentKind>,
+    pub kind: ComponentErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ComponentErrorKind {
+    /// The name contains a quote, a backslash or a control character, which
+    /// cannot be written in an include statement.
+    InvalidName(String),
+    /// libxkbcommon failed to compile the component, e.g. because a file
+    /// was not found in the include paths. The details are logged by the
+    /// context.
+    Compile,
+}
+
+impl fmt::Display for ComponentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(component) = self.component {
+            write!(f, "{component}: ")?;
+        }
+        match &self.kind {
+            ComponentErrorKind::InvalidName(name) => write!(f, "invalid component name {name:?}"),
+            ComponentErrorKind::Compile => write!(f, "failed to compile"),
+        }
+    }
+}
+
+impl Error for ComponentError {}
+
+impl Components {
+    /// Get the name of a component.
+    #[must_use]
+    pub fn get(&self, kind: ComponentKind) -> &str {
+        match kind {
+            ComponentKind::Keycodes => &self.keycodes,
+            ComponentKind::Types => &self.types,
+            ComponentKind::Compat => &self.compat,
+            ComponentKind::Symbols => &self.symbols,
+        }
+    }
+
+    /// Write the keymap including the components for which `include` is
+    /// true, leaving the other sections empty.
+    fn keymap_text(&self, include: impl Fn(ComponentKind) -> bool) -> String {
+        let mut text = String::from("xkb_keymap {\n");
+        for kind in ComponentKind::ALL {
+            let name = self.get(kind);
+            if include(kind) && !name.is_empty() {
+                text += &format!("\t{} {{ include \"{name}\" }};\n", kind.keyword());
+            } else {
+                text += &format!("\t{} {{ }};\n", kind.keyword());
+            }
+        }
+        text + "};\n"
+    }
+}
+
+impl Keymap {
+    /// Create a keymap from the names of its components.
+    ///
+    /// The components are looked up in the include paths of the context.
+    ///
+    /// # Errors
+    /// Returns an error naming the component with an invalid name, or the
+    /// component which failed to compile.
+    pub fn new_from_components(
+        context: &Context,
+        components: &Components,
+        flags: KeymapCompileFlags,
+    ) -> Result<Keymap, ComponentError> {
+        for kind in ComponentKind::ALL {
+            let name = components.get(kind);
+            if name
+                .chars()
+                .any(|c| c == '"' || c == '\\' || c.is_control())
+            {
+                return Err(ComponentError {
+                    component: Some(kind),
+                    kind: ComponentErrorKind::InvalidName(name.to_owned()),
+                });
+            }
+        }
+        let compile = |include: &dyn Fn(ComponentKind) -> bool| {
+            Keymap::new_from_string(
+                context,
+                components.keymap_text(include),
+                KEYMAP_FORMAT_TEXT_V1,
+                flags,
+            )
+        };
+        if let Some(keymap) = compile(&|_| true) {
+            return Ok(keymap);
+        }
+        let component = ComponentKind::ALL
+            .into_iter()
+            .find(|&kind| compile(&|other| other == kind).is_none());
+        Err(ComponentError {
+            component,
+            kind: ComponentErrorKind::Compile,
+        })
+    }
+}
+
+#[test]
+fn keymap_from_components() {
+    use super::{CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let mut components = Components {
+        keycodes: "evdev+aliases(qwerty)".into(),
+        types: "complete".into(),
+        compat: "complete".into(),
+        symbols: "pc+us+de:2+inet(evdev)".into(),
+    };
+    let keymap =
+        Keymap::new_from_components(&context, &components, KEYMAP_COMPILE_NO_FLAGS).unwrap();
+    assert_eq!(keymap.num_layouts(), 2);
+    assert_eq!(keymap.layout_get_name(1), "German");
+
+    components.compat = "complete+missing".into();
+    let error = Keymap::new_from_components(&context, &components, KEYMAP_COMPILE_NO_FLAGS)
+        .err()
+        .unwrap();
+    assert_eq!(error.component, Some(ComponentKind::Compat));
+    assert_eq!(error.kind, ComponentErrorKind::Compile);
+
+    components.symbols = "pc+us\"".into();
+    let error = Keymap::new_from_components(&context, &components, KEYMAP_COMPILE_NO_FLAGS)
+        .err()
+        .unwrap();
+    assert_eq!(error.component, Some(ComponentKind::Symbols));
+}
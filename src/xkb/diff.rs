@@ -0,0 +1,529 @@
+//! Semantic comparison of keymaps.
+//!
+//! `diff()` reports what changed between two keymaps from the point of view
+//! of a user: keys added or removed, keysyms, key types, modifier maps and
+//! repeat of the keys, and the layouts, modifiers and LEDs of the keymaps,
+//! including the real modifiers the virtual modifiers are mapped to and the
+//! indicator maps of the LEDs.
+//! Keys are matched by name, so that a key moved to another keycode is
+//! reported as such.
+
+use super::text::{self, SectionKind, StatementKind};
+use super::{
+    keysym_get_name, Keycode, Keymap, Keysym, LayoutIndex, LevelIndex, ModMask, MOD_INVALID,
+};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A difference between two keymaps.
+///
+/// Layouts and levels are indices, as in the rest of the API; `Display`
+/// writes them starting at 1, as in keymap files.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DiffEntry {
+    /// A layout was added, removed or renamed. The name is `None` if the
+    /// keymap does not have the layout.
+    LayoutChanged {
+        layout: LayoutIndex,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A modifier exists only in the new keymap.
+    ModifierAdded { name: String },
+    /// A modifier exists only in the old keymap.
+    ModifierRemoved { name: String },
+    /// A virtual modifier is mapped to other real modifiers, given by name.
+    ModifierMappingChanged {
+        name: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A LED exists only in the new keymap.
+    LedAdded { name: String },
+    /// A LED exists only in the old keymap.
+    LedRemoved { name: String },
+    /// The indicator map of a LED changed. The fields of the maps are given
+    /// as written in keymap files, e.g. `modifiers=Lock`.
+    IndicatorMapChanged {
+        name: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A key exists only in the new keymap.
+    KeyAdded { key: String },
+    /// A key exists only in the old keymap.
+    KeyRemoved { key: String },
+    /// A key has another keycode.
+    KeycodeChanged {
+        key: String,
+        old: Keycode,
+        new: Keycode,
+    },
+    /// A key repeats, or no longer repeats.
+    RepeatChanged { key: String, repeats: bool },
+    /// A key is mapped to other modifiers, given by name.
+    ModMapChanged {
+        key: String,
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    /// A key has another type in a layout. The name is `None` if the key
+    /// does not have the layout.
+    KeyTypeChanged {
+        key: String,
+        layout: LayoutIndex,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// A level of a key produces other keysyms. The keysyms are empty if the
+    /// key does not have the level.
+    KeysymsChanged {
+        key: String,
+        layout: LayoutIndex,
+        level: LevelIndex,
+        old: Vec<Keysym>,
+        new: Vec<Keysym>,
+    },
+}
+
+/// The differences between two keymaps, as returned by `diff()`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct KeymapDiff {
+    /// The differences, those of the keymap first, then those of the keys
+    /// in keycode order.
+    pub entries: Vec<DiffEntry>,
+}
+
+impl KeymapDiff {
+    /// Whether the keymaps are equivalent.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Get the names of the modifiers of a mask.
+fn mod_names(keymap: &Keymap, mask: ModMask) -> Vec<String> {
+    (0..keymap.num_mods().min(ModMask::BITS))
+        .filter(|idx| mask & (1 << idx) != 0)
+        .map(|idx| keymap.mod_get_name(idx).to_owned())
+        .collect()
+}
+
+fn mods(keymap: &Keymap) -> Vec<&str> {
+    (0..keymap.num_mods())
+        .map(|idx| keymap.mod_get_name(idx))
+        .collect()
+}
+
+fn leds(keymap: &Keymap) -> Vec<&str> {
+    (0..keymap.num_leds())
+        .map(|idx| keymap.led_get_name(idx))
+        .collect()
+}
+
+/// Get the indicator maps of a keymap, by LED name.
+///
+/// The keymap texts written by libxkbcommon always parse; should one fail,
+/// its indicator maps are not compared (see `Keymap::introspect_error()`).
+fn indicator_maps(keymap: &Keymap) -> HashMap<String, Vec<String>> {
+    let Ok(file) = text::parse_keymap(keymap) else {
+        return HashMap::new();
+    };
+    file.sections
+        .iter()
+        .filter(|section| section.kind == SectionKind::Compat)
+        .flat_map(|section| &section.statements)
+        .filter_map(|statement| match &statement.kind {
+            StatementKind::IndicatorMap { name, body } => {
+                Some((name.clone(), body.iter().map(ToString::to_string).collect()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Get the names of the keys of a keymap.
+fn keys(keymap: &Keymap) -> HashMap<String, Keycode> {
+    let mut keys = HashMap::new();
+    keymap.key_for_each(|keymap, key| {
+        if let Some(name) = keymap.key_get_name(key) {
+            keys.insert(name.to_owned(), key);
+        }
+    });
+    keys
+}
+
+/// Report the names of a list missing from the other one.
+fn names_diff(
+    old: &[&str],
+    new: &[&str],
+    added: impl Fn(String) -> DiffEntry,
+    removed: impl Fn(String) -> DiffEntry,
+    entries: &mut Vec<DiffEntry>,
+) {
+    let missing = |a: &[&str], b: &[&str]| {
+        a.iter()
+            .filter(|name| !name.is_empty() && !b.contains(name))
+            .map(|&name| name.to_owned())
+            .collect::<Vec<_>>()
+    };
+    entries.extend(missing(old, new).into_iter().map(removed));
+    entries.extend(missing(new, old).into_iter().map(added));
+}
+
+/// Get the keysyms of a level of a key, or none if the key does not have
+/// the layout, which libxkbcommon would wrap.
+fn key_syms(keymap: &Keymap, key: Keycode, layout: LayoutIndex, level: LevelIndex) -> &[Keysym] {
+    if layout < keymap.num_layouts_for_key(key) {
+        keymap.key_get_syms_by_level(key, layout, level)
+    } else {
+        &[]
+    }
+}
+
+fn key_diff(
+    old: &Keymap,
+    new: &Keymap,
+    key: &str,
+    codes: (Keycode, Keycode),
+    entries: &mut Vec<DiffEntry>,
+) {
+    let (old_key, new_key) = codes;
+    let key = key.to_owned();
+    if old_key != new_key {
+        entries.push(DiffEntry::KeycodeChanged {
+            key: key.clone(),
+            old: old_key,
+            new: new_key,
+        });
+    }
+    let repeats = new.key_repeats(new_key);
+    if old.key_repeats(old_key) != repeats {
+        entries.push(DiffEntry::RepeatChanged {
+            key: key.clone(),
+            repeats,
+        });
+    }
+    let old_mods = mod_names(old, old.key_get_mod_map(old_key));
+    let new_mods = mod_names(new, new.key_get_mod_map(new_key));
+    if old_mods != new_mods {
+        entries.push(DiffEntry::ModMapChanged {
+            key: key.clone(),
+            old: old_mods,
+            new: new_mods,
+        });
+    }
+    let num_layouts = old
+        .num_layouts_for_key(old_key)
+        .max(new.num_layouts_for_key(new_key));
+    for layout in 0..num_layouts {
+        let old_type = old.key_get_type(old_key, layout).map(|t| t.name.clone());
+        let new_type = new.key_get_type(new_key, layout).map(|t| t.name.clone());
+        if old_type != new_type {
+            entries.push(DiffEntry::KeyTypeChanged {
+                key: key.clone(),
+                layout,
+                old: old_type,
+                new: new_type,
+            });
+        }
+        let num_levels = old
+            .num_levels_for_key(old_key, layout)
+            .max(new.num_levels_for_key(new_key, layout));
+        for level in 0..num_levels {
+            let old_syms = key_syms(old, old_key, layout, level);
+            let new_syms = key_syms(new, new_key, layout, level);
+            if old_syms != new_syms {
+                entries.push(DiffEntry::KeysymsChanged {
+                    key: key.clone(),
+                    layout,
+                    level,
+                    old: old_syms.to_vec(),
+                    new: new_syms.to_vec(),
+                });
+            }
+        }
+    }
+}
+
+/// Compare two keymaps.
+#[must_use]
+pub fn diff(old: &Keymap, new: &Keymap) -> KeymapDiff {
+    let mut entries = Vec::new();
+    for layout in 0..old.num_layouts().max(new.num_layouts()) {
+        let name = |keymap: &Keymap| {
+            (layout < keymap.num_layouts()).then(|| keymap.layout_get_name(layout).to_owned())
+        };
+        let (old_name, new_name) = (name(old), name(new));
+        if old_name != new_name {
+            entries.push(DiffEntry::LayoutChanged {
+                layout,
+                old: old_name,
+                new: new_name,
+            });
+        }
+    }
+    names_diff(
+        &mods(old),
+        &mods(new),
+        |name| DiffEntry::ModifierAdded { name },
+        |name| DiffEntry::ModifierRemoved { name },
+        &mut entries,
+    );
+    // The first 8 modifiers are the real modifiers, mapped to themselves.
+    for idx in 8..old.num_mods() {
+        let name = old.mod_get_name(idx);
+        let new_idx = new.mod_get_index(name);
+        if new_idx == MOD_INVALID {
+            continue;
+        }
+        let old_mods = mod_names(old, old.mod_get_mask(idx));
+        let new_mods = mod_names(new, new.mod_get_mask(new_idx));
+        if old_mods != new_mods {
+            entries.push(DiffEntry::ModifierMappingChanged {
+                name: name.to_owned(),
+                old: old_mods,
+                new: new_mods,
+            });
+        }
+    }
+    names_diff(
+        &leds(old),
+        &leds(new),
+        |name| DiffEntry::LedAdded { name },
+        |name| DiffEntry::LedRemoved { name },
+        &mut entries,
+    );
+    let (old_maps, new_maps) = (indicator_maps(old), indicator_maps(new));
+    for name in leds(old) {
+        if let (Some(old_map), Some(new_map)) = (old_maps.get(name), new_maps.get(name)) {
+            if old_map != new_map {
+                entries.push(DiffEntry::IndicatorMapChanged {
+                    name: name.to_owned(),
+                    old: old_map.clone(),
+                    new: new_map.clone(),
+                });
+            }
+        }
+    }
+
+    let old_keys = keys(old);
+    let new_keys = keys(new);
+    let mut names: Vec<(&String, Keycode)> = old_keys
+        .iter()
+        .map(|(name, &key)| (name, new_keys.get(name).copied().unwrap_or(key)))
+        .chain(
+            new_keys
+                .iter()
+                .filter(|(name, _)| !old_keys.contains_key(*name))
+                .map(|(name, &key)| (name, key)),
+        )
+        .collect();
+    names.sort_by_key(|&(name, key)| (key, name));
+    for (name, _) in names {
+        match (old_keys.get(name), new_keys.get(name)) {
+            (Some(&old_key), Some(&new_key)) => {
+                key_diff(old, new, name, (old_key, new_key), &mut entries);
+            }
+            (Some(_), None) => entries.push(DiffEntry::KeyRemoved { key: name.clone() }),
+            (None, _) => entries.push(DiffEntry::KeyAdded { key: name.clone() }),
+        }
+    }
+    KeymapDiff { entries }
+}
+
+fn write_name(f: &mut fmt::Formatter<'_>, name: Option<&String>) -> fmt::Result {
+    match name {
+        Some(name) => write!(f, "{name:?}"),
+        None => f.write_str("(none)"),
+    }
+}
+
+fn write_syms(f: &mut fmt::Formatter<'_>, syms: &[Keysym]) -> fmt::Result {
+    match syms {
+        [] => f.write_str("NoSymbol"),
+        [sym] => f.write_str(&keysym_get_name(*sym)),
+        _ => {
+            let names: Vec<String> = syms.iter().map(|&sym| keysym_get_name(sym)).collect();
+            write!(f, "{{ {} }}", names.join(", "))
+        }
+    }
+}
+
+fn write_mods(f: &mut fmt::Formatter<'_>, mods: &[String]) -> fmt::Result {
+    if mods.is_empty() {
+        f.write_str("none")
+    } else {
+        f.write_str(&mods.join("+"))
+    }
+}
+
+impl fmt::Display for DiffEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiffEntry::LayoutChanged { layout, old, new } => {
+                write!(f, "layout {}: ", layout + 1)?;
+                write_name(f, old.as_ref())?;
+                f.write_str(" -> ")?;
+                write_name(f, new.as_ref())
+            }
+            DiffEntry::ModifierAdded { name } => write!(f, "+ modifier {name}"),
+            DiffEntry::ModifierRemoved { name } => write!(f, "- modifier {name}"),
+            DiffEntry::ModifierMappingChanged { name, old, new } => {
+                write!(f, "modifier {name}: ")?;
+                write_mods(f, old)?;
+                f.write_str(" -> ")?;
+                write_mods(f, new)
+            }
+            DiffEntry::LedAdded { name } => write!(f, "+ LED {name:?}"),
+            DiffEntry::LedRemoved { name } => write!(f, "- LED {name:?}"),
+            DiffEntry::IndicatorMapChanged { name, old, new } => {
+                write!(
+                    f,
+                    "LED {name:?}: {{ {} }} -> {{ {} }}",
+                    old.join("; "),
+                    new.join("; ")
+                )
+            }
+            DiffEntry::KeyAdded { key } => write!(f, "+ key <{key}>"),
+            DiffEntry::KeyRemoved { key } => write!(f, "- key <{key}>"),
+            DiffEntry::KeycodeChanged { key, old, new } => {
+                write!(f, "<{key}>: keycode {} -> {}", old.raw(), new.raw())
+            }
+            DiffEntry::RepeatChanged { key, repeats: true } => write!(f, "<{key}>: now repeats"),
+            DiffEntry::RepeatChanged {
+                key,
+                repeats: false,
+            } => {
+                write!(f, "<{key}>: no longer repeats")
+            }
+            DiffEntry::ModMapChanged { key, old, new } => {
+                write!(f, "<{key}>: modifiers ")?;
+                write_mods(f, old)?;
+                f.write_str(" -> ")?;
+                write_mods(f, new)
+            }
+            DiffEntry::KeyTypeChanged {
+                key,
+                layout,
+                old,
+                new,
+            } => {
+                write!(f, "<{key}> layout {}: type ", layout + 1)?;
+                write_name(f, old.as_ref())?;
+                f.write_str(" -> ")?;
+                write_name(f, new.as_ref())
+            }
+            DiffEntry::KeysymsChanged {
+                key,
+                layout,
+                level,
+                old,
+                new,
+            } => {
+                write!(f, "<{key}> layout {} level {}: ", layout + 1, level + 1)?;
+                write_syms(f, old)?;
+                f.write_str(" -> ")?;
+                write_syms(f, new)
+            }
+        }
+    }
+}
+
+/// Writes one difference per line.
+impl fmt::Display for KeymapDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{entry}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn keymap_diff() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(0);
+    let keymap = |layout: &str, options: &str| {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layout,
+            "",
+            Some(options.into()),
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let us = keymap("us", "");
+    assert!(diff(&us, &keymap("us", "")).is_empty());
+    let changes = diff(&us, &keymap("de", "ctrl:nocaps"));
+    assert!(changes.entries.contains(&DiffEntry::LayoutChanged {
+        layout: 0,
+        old: Some("English (US)".into()),
+        new: Some("German".into()),
+    }));
+    assert!(changes.entries.contains(&DiffEntry::ModMapChanged {
+        key: "CAPS".into(),
+        old: vec!["Lock".into()],
+        new: vec!["Control".into()],
+    }));
+    let y = changes
+        .entries
+        .iter()
+        .find(|entry| matches!(entry, DiffEntry::KeysymsChanged { key, level: 0, .. } if key == "AD06"))
+        .unwrap();
+    assert_eq!(y.to_string(), "<AD06> layout 1 level 1: y -> z");
+}
+
+#[test]
+fn keymap_diff_mappings() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS, KEYMAP_FORMAT_TEXT_V1};
+
+    let context = Context::new(0);
+    let keymap = |modifier: &str, led: &str| {
+        let text = format!(
+            r#"xkb_keymap {{
+                xkb_keycodes {{ <RALT> = 108; indicator 1 = "Caps Lock"; }};
+                xkb_types {{ include "complete" }};
+                xkb_compat {{
+                    include "complete"
+                    indicator "Caps Lock" {{ modifiers= {led}; }};
+                }};
+                xkb_symbols {{
+                    key <RALT> {{ [ ISO_Level3_Shift ] }};
+                    modifier_map {modifier} {{ <RALT> }};
+                }};
+            }};"#
+        );
+        Keymap::new_from_string(
+            &context,
+            text,
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let old = keymap("Mod5", "Lock");
+    assert!(diff(&old, &keymap("Mod5", "Lock")).is_empty());
+    let changes = diff(&old, &keymap("Mod4", "Shift"));
+    let level3 = DiffEntry::ModifierMappingChanged {
+        name: "LevelThree".into(),
+        old: vec!["Mod5".into()],
+        new: vec!["Mod4".into()],
+    };
+    assert!(changes.entries.contains(&level3));
+    assert_eq!(level3.to_string(), "modifier LevelThree: Mod5 -> Mod4");
+    let led = changes
+        .entries
+        .iter()
+        .find(|entry| matches!(entry, DiffEntry::IndicatorMapChanged { .. }))
+        .unwrap();
+    assert_eq!(
+        led.to_string(),
+        "LED \"Caps Lock\": { modifiers=Lock } -> { modifiers=Shift }"
+    );
+}
@@ -0,0 +1,674 @@
+//! Editing of compiled keymaps.
+//!
+//! A `Keymap` cannot be changed once compiled. `KeymapEditor` records typed
+//! edits against the text form of a keymap, and compiles the edited text to
+//! a new `Keymap`. When the result does not compile, the edits are bisected
+//! to find the one that broke it.
+
+use super::canonical::name_groups;
+use super::introspect::group_index;
+use super::text::{
+    self, Expr, ExprKind, KeymapFile, ParseError, SectionKind, Span, Statement, StatementKind,
+    VarDef,
+};
+use super::{
+    keysym_get_name, Context, Keycode, Keymap, Keysym, LayoutIndex, LevelIndex,
+    KEYMAP_COMPILE_NO_FLAGS, KEYMAP_FORMAT_TEXT_V1,
+};
+use std::error::Error;
+use std::fmt;
+
+/// Maximum number of layouts of a keymap.
+const MAX_LAYOUTS: LayoutIndex = 4;
+
+/// Maximum number of levels of the key types libxkbcommon picks for keys
+/// without an explicit type.
+const AUTOMATIC_TYPE_LEVELS: LevelIndex = 4;
+
+/// An edit of a keymap.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Edit {
+    /// Set the keysyms of a level of a key. No keysyms clears the level.
+    ///
+    /// The level must exist in the type of the key, or for keys without an
+    /// explicit type, be one of the first four levels.
+    SetSymbols {
+        key: Keycode,
+        layout: LayoutIndex,
+        level: LevelIndex,
+        keysyms: Vec<Keysym>,
+    },
+    /// Set the type of a key in a layout.
+    SetKeyType {
+        key: Keycode,
+        layout: LayoutIndex,
+        name: String,
+    },
+    /// Set whether a key repeats.
+    SetRepeat { key: Keycode, repeats: bool },
+    /// Append a layout, empty or with the keys of another layout.
+    AddLayout {
+        name: String,
+        copy_from: Option<LayoutIndex>,
+    },
+    /// Remove a layout; the following layouts move down by one.
+    RemoveLayout { layout: LayoutIndex },
+}
+
+/// Error of `KeymapEditor::compile()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditError {
+    /// Index of the edit causing the error, or `None` if the keymap does not
+    /// compile without edits.
+    pub edit: Option<usize>,
+    pub kind: EditErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EditErrorKind {
+    /// The key does not exist in the keymap.
+    UnknownKey(Keycode),
+    /// The key type does not exist in the keymap.
+    UnknownType(String),
+    /// The layout does not exist in the keymap.
+    InvalidLayout(LayoutIndex),
+    /// The level does not exist in the type of the key.
+    InvalidLevel(LevelIndex),
+    /// The keymap would have too many layouts, or none.
+    LayoutCount,
+    /// libxkbcommon failed to compile the edited keymap.
+    Compile,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(edit) = self.edit {
+            write!(f, "edit {edit}: ")?;
+        }
+        match &self.kind {
+            EditErrorKind::UnknownKey(key) => write!(f, "unknown key {}", key.raw()),
+            EditErrorKind::UnknownType(name) => write!(f, "unknown key type \"{name}\""),
+            EditErrorKind::InvalidLayout(layout) => write!(f, "invalid layout {layout}"),
+            EditErrorKind::InvalidLevel(level) => write!(f, "invalid level {level}"),
+            EditErrorKind::LayoutCount => {
+                write!(f, "a keymap has between 1 and {MAX_LAYOUTS} layouts")
+            }
+            EditErrorKind::Compile => write!(f, "the keymap failed to compile"),
+        }
+    }
+}
+
+impl Error for EditError {}
+
+pub(crate) fn expr(kind: ExprKind) -> Expr {
+    Expr {
+        kind,
+        span: Span::default(),
+    }
+}
+
+pub(crate) fn ident(name: &str) -> Expr {
+    expr(ExprKind::Ident(name.to_owned()))
+}
+
+pub(crate) fn group(layout: LayoutIndex) -> Expr {
+    ident(&format!("Group{}", layout + 1))
+}
+
+fn keysym(sym: Keysym) -> Expr {
+    let name = keysym_get_name(sym);
+    if name.starts_with("0x") {
+        expr(ExprKind::Integer {
+            value: i64::from(sym.raw()),
+            hex: true,
+        })
+    } else {
+        ident(&name)
+    }
+}
+
+/// Get the layout of a field of a key body or of a `name[GroupN]`
+/// definition, or `None` if it applies to all the layouts.
+pub(crate) fn layout_of(var: &VarDef) -> Option<LayoutIndex> {
+    var.index().and_then(group_index)
+}
+
+pub(crate) fn is_field(var: &VarDef, field: &str) -> bool {
+    var.field().is_some_and(|f| f.eq_ignore_ascii_case(field))
+}
+
+/// Get a field of a key body, adding it if it does not exist.
+fn field<'a>(
+    body: &'a mut Vec<VarDef>,
+    field: &str,
+    layout: Option<LayoutIndex>,
+    default: Expr,
+) -> &'a mut Expr {
+    let idx = match body.iter().position(|var| {
+        is_field(var, field)
+            && var.index().is_some() == layout.is_some()
+            && layout_of(var) == layout
+    }) {
+        Some(idx) => idx,
+        None => {
+            let name = match layout {
+                Some(layout) => ExprKind::ArrayRef {
+                    elem: None,
+                    field: field.to_owned(),
+                    index: Box::new(group(layout)),
+                },
+                None => ExprKind::Ident(field.to_owned()),
+            };
+            body.push(VarDef {
+                name: Some(expr(name)),
+                value: default,
+                span: Span::default(),
+            });
+            body.len() - 1
+        }
+    };
+    &mut body[idx].value
+}
+
+/// Move the fields of a layout to another layout.
+pub(crate) fn set_layout(var: &mut VarDef, layout: LayoutIndex) {
+    if let Some(Expr {
+        kind: ExprKind::ArrayRef { index, .. },
+        ..
+    }) = &mut var.name
+    {
+        **index = group(layout);
+    }
+}
+
+/// Keymap text being edited.
+struct Target<'a> {
+    keymap: &'a Keymap,
+    file: KeymapFile,
+    num_layouts: LayoutIndex,
+}
+
+impl Target<'_> {
+    fn symbols(&mut self) -> &mut Vec<Statement> {
+        let idx = match self
+            .file
+            .sections
+            .iter()
+            .position(|section| section.kind == SectionKind::Symbols)
+        {
+            Some(idx) => idx,
+            None => {
+                self.file.sections.push(text::Section {
+                    flags: Vec::new(),
+                    kind: SectionKind::Symbols,
+                    name: None,
+                    statements: Vec::new(),
+                    span: Span::default(),
+                });
+                self.file.sections.len() - 1
+            }
+        };
+        &mut self.file.sections[idx].statements
+    }
+
+    fn key_body(&mut self, key: Keycode) -> Result<&mut Vec<VarDef>, EditErrorKind> {
+        let name = self
+            .keymap
+            .key_get_name(key)
+            .ok_or(EditErrorKind::UnknownKey(key))?
+            .to_owned();
+        let statements = self.symbols();
+        let idx = match statements
+            .iter()
+            .position(|s| matches!(&s.kind, StatementKind::Key { name: n, .. } if *n == name))
+        {
+            Some(idx) => idx,
+            None => {
+                statements.push(Statement {
+                    kind: StatementKind::Key {
+                        name,
+                        body: Vec::new(),
+                    },
+                    span: Span::default(),
+                });
+                statements.len() - 1
+            }
+        };
+        match &mut statements[idx].kind {
+            StatementKind::Key { body, .. } => Ok(body),
+            _ => unreachable!(),
+        }
+    }
+
+    fn check_layout(&self, layout: LayoutIndex) -> Result<(), EditErrorKind> {
+        if layout < self.num_layouts {
+            Ok(())
+        } else {
+            Err(EditErrorKind::InvalidLayout(layout))
+        }
+    }
+
+    /// Get the number of levels of the type of a key in a layout: its
+    /// explicit type, or else the largest automatic type.
+    fn key_levels(
+        &mut self,
+        key: Keycode,
+        layout: LayoutIndex,
+    ) -> Result<LevelIndex, EditErrorKind> {
+        let body = self.key_body(key)?;
+        // A type for the layout overrides a type for all the layouts.
+        let name = body
+            .iter()
+            .filter(|var| is_field(var, "type"))
+            .filter(|var| var.index().is_none() || layout_of(var) == Some(layout))
+            .max_by_key(|var| var.index().is_some())
+            .and_then(|var| match &var.value.kind {
+                ExprKind::String(name) => Some(name.clone()),
+                _ => None,
+            });
+        let Some(name) = name else {
+            return Ok(AUTOMATIC_TYPE_LEVELS);
+        };
+        Ok(self
+            .keymap
+            .key_types()
+            .iter()
+            .find(|t| t.name == name)
+            .map_or(LevelIndex::MAX, |t| t.num_levels))
+    }
+
+    fn apply(&mut self, edit: &Edit) -> Result<(), EditErrorKind> {
+        match edit {
+            Edit::SetSymbols {
+                key,
+                layout,
+                level,
+                keysyms,
+            } => {
+                self.check_layout(*layout)?;
+                if *level >= self.key_levels(*key, *layout)? {
+                    return Err(EditErrorKind::InvalidLevel(*level));
+                }
+                let body = self.key_body(*key)?;
+                let value = match keysyms.as_slice() {
+                    [] => ident("NoSymbol"),
+                    [sym] => keysym(*sym),
+                    syms => expr(ExprKind::Braced(
+                        syms.iter().map(|&sym| keysym(sym)).collect(),
+                    )),
+                };
+                let list = field(
+                    body,
+                    "symbols",
+                    Some(*layout),
+                    expr(ExprKind::List(Vec::new())),
+                );
+                let ExprKind::List(items) = &mut list.kind else {
+                    *list = expr(ExprKind::List(Vec::new()));
+                    return self.apply(edit);
+                };
+                let level = *level as usize;
+                if items.len() <= level {
+                    items.resize(level + 1, ident("NoSymbol"));
+                }
+                items[level] = value;
+            }
+            Edit::SetKeyType { key, layout, name } => {
+                self.check_layout(*layout)?;
+                if !self.keymap.key_types().iter().any(|t| t.name == *name) {
+                    return Err(EditErrorKind::UnknownType(name.clone()));
+                }
+                let num_layouts = self.num_layouts;
+                let body = self.key_body(*key)?;
+                // A type for all the layouts becomes a type per layout.
+                if let Some(idx) = body
+                    .iter()
+                    .position(|var| is_field(var, "type") && var.index().is_none())
+                {
+                    let all = body.remove(idx).value;
+                    for other in 0..num_layouts {
+                        field(body, "type", Some(other), all.clone());
+                    }
+                }
+                *field(body, "type", Some(*layout), ident("")) =
+                    expr(ExprKind::String(name.clone()));
+            }
+            Edit::SetRepeat { key, repeats } => {
+                let body = self.key_body(*key)?;
+                body.retain(|var| !is_field(var, "repeats"));
+                *field(body, "repeat", None, ident("")) =
+                    ident(if *repeats { "True" } else { "False" });
+            }
+            Edit::AddLayout { name, copy_from } => {
+                if let Some(source) = copy_from {
+                    self.check_layout(*source)?;
+                }
+                if self.num_layouts >= MAX_LAYOUTS {
+                    return Err(EditErrorKind::LayoutCount);
+                }
+                let layout = self.num_layouts;
+                self.num_layouts += 1;
+                let statements = self.symbols();
+                let name_var = VarDef {
+                    name: Some(expr(ExprKind::ArrayRef {
+                        elem: None,
+                        field: "name".to_owned(),
+                        index: Box::new(group(layout)),
+                    })),
+                    value: expr(ExprKind::String(name.clone())),
+                    span: Span::default(),
+                };
+                let position = statements
+                    .iter()
+                    .rposition(
+                        |s| matches!(&s.kind, StatementKind::Var(var) if is_field(var, "name")),
+                    )
+                    .map_or(0, |idx| idx + 1);
+                statements.insert(
+                    position,
+                    Statement {
+                        kind: StatementKind::Var(name_var),
+                        span: Span::default(),
+                    },
+                );
+                let Some(source) = *copy_from else {
+                    return Ok(());
+                };
+                for statement in statements {
+                    if let StatementKind::Key { body, .. } = &mut statement.kind {
+                        let copies: Vec<VarDef> = body
+                            .iter()
+                            .filter(|var| layout_of(var) == Some(source))
+                            .cloned()
+                            .map(|mut var| {
+                                set_layout(&mut var, layout);
+                                var
+                            })
+                            .collect();
+                        body.extend(copies);
+                    }
+                }
+            }
+            Edit::RemoveLayout { layout } => {
+                self.check_layout(*layout)?;
+                if self.num_layouts == 1 {
+                    return Err(EditErrorKind::LayoutCount);
+                }
+                self.num_layouts -= 1;
+                let layout = *layout;
+                let shift = |vars: &mut Vec<VarDef>| {
+                    vars.retain(|var| layout_of(var) != Some(layout));
+                    for var in vars {
+                        match layout_of(var) {
+                            Some(other) if other > layout => set_layout(var, other - 1),
+                            _ => {}
+                        }
+                    }
+                };
+                let statements = self.symbols();
+                let mut names: Vec<VarDef> = Vec::new();
+                statements.retain(|s| match &s.kind {
+                    StatementKind::Var(var) if is_field(var, "name") && var.index().is_some() => {
+                        names.push(var.clone());
+                        false
+                    }
+                    _ => true,
+                });
+                shift(&mut names);
+                for (i, var) in names.into_iter().enumerate() {
+                    statements.insert(
+                        i,
+                        Statement {
+                            kind: StatementKind::Var(var),
+                            span: Span::default(),
+                        },
+                    );
+                }
+                for statement in statements {
+                    if let StatementKind::Key { body, .. } = &mut statement.kind {
+                        shift(body);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Edits a keymap, producing a new keymap.
+///
+/// ```
+/// # use xkbcommon::xkb;
+/// let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+/// let keymap = xkb::Keymap::new_from_names(
+///     &context,
+///     "evdev",
+///     "pc105",
+///     "us",
+///     "",
+///     None,
+///     xkb::KEYMAP_COMPILE_NO_FLAGS,
+/// )
+/// .unwrap();
+/// let caps = keymap.key_by_name("CAPS").unwrap();
+/// let keymap = xkb::KeymapEditor::new(&context, &keymap)?
+///     .set_key_symbols(caps, 0, 0, &[xkb::Keysym::Escape])
+///     .set_key_repeat(caps, false)
+///     .compile()?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct KeymapEditor {
+    context: Context,
+    keymap: Keymap,
+    file: KeymapFile,
+    edits: Vec<Edit>,
+}
+
+impl KeymapEditor {
+    /// Start editing a keymap. The edited keymap is compiled in `context`.
+    ///
+    /// # Errors
+    /// Returns an error if the text of the keymap cannot be parsed.
+    pub fn new(context: &Context, keymap: &Keymap) -> Result<KeymapEditor, ParseError> {
+        let mut file = text::parse_keymap(keymap)?;
+        for section in &mut file.sections {
+            for statement in &mut section.statements {
+                if let StatementKind::Key { body, .. } = &mut statement.kind {
+                    name_groups(body);
+                }
+            }
+        }
+        Ok(KeymapEditor {
+            context: context.clone(),
+            keymap: keymap.clone(),
+            file,
+            edits: Vec::new(),
+        })
+    }
+
+    /// Get the edits, in the order they apply.
+    #[must_use]
+    pub fn edits(&self) -> &[Edit] {
+        &self.edits
+    }
+
+    /// Add an edit.
+    pub fn edit(&mut self, edit: Edit) -> &mut KeymapEditor {
+        self.edits.push(edit);
+        self
+    }
+
+    /// Set the keysyms of a level of a key. No keysyms clears the level.
+    pub fn set_key_symbols(
+        &mut self,
+        key: Keycode,
+        layout: LayoutIndex,
+        level: LevelIndex,
+        keysyms: &[Keysym],
+    ) -> &mut KeymapEditor {
+        self.edit(Edit::SetSymbols {
+            key,
+            layout,
+            level,
+            keysyms: keysyms.to_vec(),
+        })
+    }
+
+    /// Set the type of a key in a layout, e.g. `"TWO_LEVEL"`. The type must
+    /// exist in the keymap, see `Keymap::key_types()`.
+    pub fn set_key_type(
+        &mut self,
+        key: Keycode,
+        layout: LayoutIndex,
+        name: &str,
+    ) -> &mut KeymapEditor {
+        self.edit(Edit::SetKeyType {
+            key,
+            layout,
+            name: name.to_owned(),
+        })
+    }
+
+    /// Set whether a key repeats.
+    pub fn set_key_repeat(&mut self, key: Keycode, repeats: bool) -> &mut KeymapEditor {
+        self.edit(Edit::SetRepeat { key, repeats })
+    }
+
+    /// Append a layout with the keys of another layout, or without keys.
+    ///
+    /// libxkbcommon only counts the layouts in which keys have keysyms or
+    /// actions: a layout added without keys only exists in the new keymap
+    /// once keysyms are set in it.
+    pub fn add_layout(&mut self, name: &str, copy_from: Option<LayoutIndex>) -> &mut KeymapEditor {
+        self.edit(Edit::AddLayout {
+            name: name.to_owned(),
+            copy_from,
+        })
+    }
+
+    /// Remove a layout. The following layouts move down by one.
+    pub fn remove_layout(&mut self, layout: LayoutIndex) -> &mut KeymapEditor {
+        self.edit(Edit::RemoveLayout { layout })
+    }
+
+    /// Apply the first `count` edits to the keymap text.
+    fn apply(&self, count: usize) -> Result<String, EditError> {
+        let mut target = Target {
+            keymap: &self.keymap,
+            file: self.file.clone(),
+            num_layouts: self.keymap.num_layouts(),
+        };
+        for (idx, edit) in self.edits[..count].iter().enumerate() {
+            target.apply(edit).map_err(|kind| EditError {
+                edit: Some(idx),
+                kind,
+            })?;
+        }
+        Ok(target.file.to_string())
+    }
+
+    fn compiles(&self, count: usize) -> Result<Option<Keymap>, EditError> {
+        let text = self.apply(count)?;
+        Ok(Keymap::new_from_string(
+            &self.context,
+            text,
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        ))
+    }
+
+    /// Compile the edited keymap.
+    ///
+    /// # Errors
+    /// Returns an error naming the first invalid edit. If libxkbcommon fails
+    /// to compile the result, the edits are bisected to find the first one
+    /// after which the keymap no longer compiles.
+    pub fn compile(&self) -> Result<Keymap, EditError> {
+        if let Some(keymap) = self.compiles(self.edits.len())? {
+            return Ok(keymap);
+        }
+        let error = |edit| EditError {
+            edit,
+            kind: EditErrorKind::Compile,
+        };
+        if self.compiles(0)?.is_none() {
+            return Err(error(None));
+        }
+        let (mut good, mut bad) = (0, self.edits.len());
+        while bad - good > 1 {
+            let mid = (good + bad) / 2;
+            if self.compiles(mid)?.is_some() {
+                good = mid;
+            } else {
+                bad = mid;
+            }
+        }
+        Err(error(Some(bad - 1)))
+    }
+}
+
+#[test]
+fn keymap_editor() {
+    use super::keysyms::{KEY_Escape, KEY_a};
+    use super::CONTEXT_NO_FLAGS;
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let caps = keymap.key_by_name("CAPS").unwrap();
+    let edited = KeymapEditor::new(&context, &keymap)
+        .unwrap()
+        .set_key_symbols(caps, 1, 0, &[Keysym::new(KEY_Escape)])
+        .set_key_repeat(caps, false)
+        .remove_layout(0)
+        .add_layout("Copy", Some(0))
+        .compile()
+        .unwrap();
+    assert_eq!(edited.num_layouts(), 2);
+    assert_eq!(edited.layout_get_name(0), "German");
+    assert_eq!(edited.layout_get_name(1), "Copy");
+    assert_eq!(
+        edited.key_get_syms_by_level(caps, 0, 0),
+        [Keysym::new(KEY_Escape)]
+    );
+    assert!(!edited.key_repeats(caps));
+
+    let Err(error) = KeymapEditor::new(&context, &keymap)
+        .unwrap()
+        .set_key_symbols(caps, 0, 0, &[Keysym::new(KEY_a)])
+        .set_key_type(caps, 2, "TWO_LEVEL")
+        .compile()
+    else {
+        panic!("the edit of a missing layout compiled");
+    };
+    assert_eq!(error.edit, Some(1));
+    assert_eq!(error.kind, EditErrorKind::InvalidLayout(2));
+
+    let a = keymap.key_by_name("AC01").unwrap();
+    let Err(error) = KeymapEditor::new(&context, &keymap)
+        .unwrap()
+        .set_key_type(a, 0, "TWO_LEVEL")
+        .set_key_symbols(a, 0, 2, &[Keysym::new(KEY_a)])
+        .compile()
+    else {
+        panic!("the edit of a missing level compiled");
+    };
+    assert_eq!(error.edit, Some(1));
+    assert_eq!(error.kind, EditErrorKind::InvalidLevel(2));
+    let Err(error) = KeymapEditor::new(&context, &keymap)
+        .unwrap()
+        .set_key_symbols(a, 0, 4, &[Keysym::new(KEY_a)])
+        .compile()
+    else {
+        panic!("the edit of a missing level compiled");
+    };
+    assert_eq!(error.kind, EditErrorKind::InvalidLevel(4));
+}
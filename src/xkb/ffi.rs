@@ -213,6 +213,10 @@ extern "C" {
         name: *const c_char,
     ) -> xkb_mod_index_t;
 
+    #[cfg(feature = "v1_10")]
+    pub fn xkb_keymap_mod_get_mask(keymap: *mut xkb_keymap, idx: xkb_mod_index_t)
+        -> xkb_mod_mask_t;
+
     pub fn xkb_keymap_num_layouts(keymap: *mut xkb_keymap) -> xkb_layout_index_t;
 
     pub fn xkb_keymap_layout_get_name(
@@ -292,6 +296,19 @@ extern "C" {
         locked_layout: xkb_layout_index_t,
     ) -> xkb_state_component;
 
+    #[cfg(feature = "v1_8")]
+    pub fn xkb_state_update_latched_locked(
+        state: *mut xkb_state,
+        affect_latched_mods: xkb_mod_mask_t,
+        latched_mods: xkb_mod_mask_t,
+        affect_latched_layout: bool,
+        latched_layout: i32,
+        affect_locked_mods: xkb_mod_mask_t,
+        locked_mods: xkb_mod_mask_t,
+        affect_locked_layout: bool,
+        locked_layout: i32,
+    ) -> xkb_state_component;
+
     pub fn xkb_state_key_get_syms(
         state: *mut xkb_state,
         key: xkb_keycode_t,
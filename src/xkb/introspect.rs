@@ -0,0 +1,781 @@
+//! Keymap introspection beyond the C API.
+//!
+//! libxkbcommon does not expose the key types and the key actions of a
+//! keymap. This module derives them from the text form of the keymap, parsed
+//! with `xkb::text`. The information is computed on first use, which dumps
+//! and parses the whole keymap, and cached for all the `Keymap` handles on
+//! the same keymap in the thread, including those returned by
+//! `State::get_keymap()`. If the text cannot be parsed, the information is
+//! missing, and `Keymap::introspect_error()` returns the parse error.
+
+use super::actions::Action;
+use super::keysyms::{KEY_KP_Equal, KEY_KP_Space, KEY_NoSymbol, KEY_0};
+use super::text::{self, Expr, ExprKind, ParseError, SectionKind, StatementKind, VarDef};
+use super::{
+    keysym_from_name, Keycode, Keymap, Keysym, LayoutIndex, LevelIndex, ModMask,
+    KEYMAP_FORMAT_TEXT_V1, KEYSYM_NO_FLAGS,
+};
+use std::collections::HashMap;
+
+/// An entry of the modifier map of a key type.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyTypeEntry {
+    /// Modifiers selecting this entry, as a mask of modifier indices.
+    pub modifiers: ModMask,
+    /// Level selected by the modifiers.
+    pub level: LevelIndex,
+    /// Modifiers preserved, i.e. not consumed, by this entry, as a mask of
+    /// modifier indices.
+    pub preserve: ModMask,
+}
+
+/// A key type, which determines the shift level of a key from the active
+/// modifiers.
+///
+/// Modifier masks are masks of modifier indices, as returned by
+/// `Keymap::mod_get_index()`. They may include virtual modifiers, such as
+/// `LevelThree`: use `Keymap::mod_get_mask()` to get the real modifiers
+/// they are mapped to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyType {
+    /// Name of the type, e.g. `"TWO_LEVEL"`.
+    pub name: String,
+    /// Modifiers significant for the type.
+    pub modifiers: ModMask,
+    /// Map from modifier combinations to levels. Combinations which are not
+    /// listed select the first level.
+    pub entries: Vec<KeyTypeEntry>,
+    /// Names of the levels, e.g. `"Base"`, `"Shift"` or `"AltGr"`, indexed by
+    /// level. Levels without a name have an empty name.
+    pub level_names: Vec<String>,
+    /// Number of levels of the type.
+    pub num_levels: LevelIndex,
+}
+
+impl KeyType {
+    /// Get the name of a level of the type, or `None` if it has no name.
+    #[must_use]
+    pub fn level_name(&self, level: LevelIndex) -> Option<&str> {
+        self.level_names
+            .get(level as usize)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+}
+
+/// Information derived from the text form of a keymap.
+#[derive(Default)]
+pub(crate) struct KeymapInfo {
+    types: Vec<KeyType>,
+    /// Index of the type of each layout of the keys, or `None` if the type
+    /// is not found.
+    key_types: HashMap<Keycode, Vec<Option<usize>>>,
+    /// Actions of each level of each layout of the keys.
+    key_actions: HashMap<Keycode, Vec<Vec<Vec<Action>>>>,
+    /// Modifiers the keys are mapped to by `modifier_map` statements.
+    modmaps: HashMap<Keycode, ModMask>,
+    /// Indices of the interprets applied to the keys, in the order of the
+    /// compat sections.
+    key_interprets: HashMap<Keycode, Vec<usize>>,
+    /// Error parsing the keymap text, in which case the rest is empty.
+    error: Option<ParseError>,
+}
+
+/// Parse `GroupN` or `N` to a layout index.
+pub(crate) fn group_index(expr: &Expr) -> Option<LayoutIndex> {
+    let group = match &expr.kind {
+        ExprKind::Ident(ident) if ident.len() > 5 && ident[..5].eq_ignore_ascii_case("group") => {
+            ident[5..].parse().ok()?
+        }
+        _ => u32::try_from(expr.as_integer()?).ok()?,
+    };
+    group.checked_sub(1)
+}
+
+/// Parse `N` to a level index.
+pub(crate) fn level_index(expr: &Expr) -> Option<LevelIndex> {
+    u32::try_from(expr.as_integer()?).ok()?.checked_sub(1)
+}
+
+/// Resolve a modifier expression, such as `Shift+LevelThree`, to a mask of
+/// modifier indices.
+pub(crate) fn mod_mask(keymap: &Keymap, expr: &Expr) -> ModMask {
+    match &expr.kind {
+        ExprKind::Ident(name) if name.eq_ignore_ascii_case("none") => 0,
+        ExprKind::Ident(name) if name.eq_ignore_ascii_case("all") => {
+            ModMask::MAX >> (ModMask::BITS - keymap.num_mods().clamp(1, ModMask::BITS))
+        }
+        ExprKind::Ident(name) => match keymap.mod_get_index(name.as_str()) {
+            idx if idx < ModMask::BITS => 1 << idx,
+            _ => 0,
+        },
+        ExprKind::Binary(_, lhs, rhs) => mod_mask(keymap, lhs) | mod_mask(keymap, rhs),
+        _ => expr
+            .as_integer()
+            .and_then(|mask| ModMask::try_from(mask).ok())
+            .unwrap_or(0),
+    }
+}
+
+/// Resolve a keysym of a symbols list, or `None` for several keysyms.
+pub(crate) fn keysym(expr: &Expr) -> Option<Keysym> {
+    match &expr.kind {
+        ExprKind::Ident(name) => Some(keysym_from_name(name, KEYSYM_NO_FLAGS)),
+        ExprKind::Integer { value, .. } => Some(match u32::try_from(*value) {
+            Ok(digit @ 0..=9) => Keysym::new(KEY_0 + digit),
+            Ok(value) => Keysym::new(value),
+            Err(_) => Keysym::new(KEY_NoSymbol),
+        }),
+        ExprKind::Braced(syms) => match syms.as_slice() {
+            [sym] => keysym(sym),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_lower(keysym: Keysym) -> bool {
+    keysym.key_char().is_some_and(|c| {
+        let mut upper = c.to_uppercase();
+        c.is_lowercase() && upper.next() != Some(c) && upper.next().is_none()
+    })
+}
+
+fn is_upper(keysym: Keysym) -> bool {
+    keysym.key_char().is_some_and(|c| {
+        let mut lower = c.to_lowercase();
+        c.is_uppercase() && lower.next() != Some(c) && lower.next().is_none()
+    })
+}
+
+fn is_keypad(keysym: Keysym) -> bool {
+    (KEY_KP_Space..=KEY_KP_Equal).contains(&keysym.raw())
+}
+
+/// Get the name of the type libxkbcommon assigns to a group of a key
+/// without explicit type, from its keysyms.
+fn automatic_type(syms: &[Keysym], width: usize) -> &'static str {
+    let sym = |level: usize| {
+        syms.get(level)
+            .copied()
+            .unwrap_or(Keysym::new(KEY_NoSymbol))
+    };
+    match width {
+        0 | 1 => "ONE_LEVEL",
+        2 if is_lower(sym(0)) && is_upper(sym(1)) => "ALPHABETIC",
+        2 if is_keypad(sym(0)) || is_keypad(sym(1)) => "KEYPAD",
+        2 => "TWO_LEVEL",
+        _ if is_lower(sym(0)) && is_upper(sym(1)) => {
+            if is_lower(sym(2)) && is_upper(sym(3)) {
+                "FOUR_LEVEL_ALPHABETIC"
+            } else {
+                "FOUR_LEVEL_SEMIALPHABETIC"
+            }
+        }
+        _ if is_keypad(sym(0)) || is_keypad(sym(1)) => "FOUR_LEVEL_KEYPAD",
+        _ => "FOUR_LEVEL",
+    }
+}
+
+fn key_type(keymap: &Keymap, name: &str, body: &[VarDef]) -> KeyType {
+    let mut key_type = KeyType {
+        name: name.to_owned(),
+        modifiers: 0,
+        entries: Vec::new(),
+        level_names: Vec::new(),
+        num_levels: 1,
+    };
+    for var in body {
+        let index = var.index();
+        match (var.field(), index) {
+            (Some("modifiers"), None) => key_type.modifiers = mod_mask(keymap, &var.value),
+            (Some("map"), Some(mods)) => {
+                let modifiers = mod_mask(keymap, mods);
+                let Some(level) = level_index(&var.value) else {
+                    continue;
+                };
+                match key_type
+                    .entries
+                    .iter_mut()
+                    .find(|entry| entry.modifiers == modifiers)
+                {
+                    Some(entry) => entry.level = level,
+                    None => key_type.entries.push(KeyTypeEntry {
+                        modifiers,
+                        level,
+                        preserve: 0,
+                    }),
+                }
+            }
+            (Some("preserve"), Some(mods)) => {
+                let modifiers = mod_mask(keymap, mods);
+                let preserve = mod_mask(keymap, &var.value);
+                match key_type
+                    .entries
+                    .iter_mut()
+                    .find(|entry| entry.modifiers == modifiers)
+                {
+                    Some(entry) => entry.preserve = preserve,
+                    None => key_type.entries.push(KeyTypeEntry {
+                        modifiers,
+                        level: 0,
+                        preserve,
+                    }),
+                }
+            }
+            (Some("level_name" | "levelname"), Some(level)) => {
+                let (Some(level), Some(name)) = (level_index(level), var.value.as_string()) else {
+                    continue;
+                };
+                let level = level as usize;
+                if key_type.level_names.len() <= level {
+                    key_type.level_names.resize(level + 1, String::new());
+                }
+                key_type.level_names[level] = name.to_owned();
+            }
+            _ => {}
+        }
+    }
+    let max_level = key_type.entries.iter().map(|entry| entry.level + 1).max();
+    key_type.num_levels = max_level
+        .unwrap_or(1)
+        .max(key_type.level_names.len() as LevelIndex)
+        .max(1);
+    key_type
+}
+
+/// Explicit types and keysyms of the groups of a key, from the body of its
+/// `key` statement.
+#[derive(Default)]
+struct KeyGroups {
+    default_type: Option<String>,
+    types: HashMap<LayoutIndex, String>,
+    syms: HashMap<LayoutIndex, Vec<Option<Keysym>>>,
+    widths: HashMap<LayoutIndex, usize>,
+    actions: HashMap<LayoutIndex, Vec<Vec<Expr>>>,
+}
+
+impl KeyGroups {
+    fn new(body: &[VarDef]) -> KeyGroups {
+        let mut groups = KeyGroups::default();
+        for var in body {
+            let group = match var.index() {
+                Some(index) => match group_index(index) {
+                    Some(group) => group,
+                    None => continue,
+                },
+                None => 0,
+            };
+            match (var.field(), &var.value.kind) {
+                (Some("type"), _) => {
+                    let Some(name) = var.value.as_string() else {
+                        continue;
+                    };
+                    if var.index().is_some() {
+                        groups.types.insert(group, name.to_owned());
+                    } else {
+                        groups.default_type = Some(name.to_owned());
+                    }
+                }
+                (None | Some("symbols"), ExprKind::List(items)) => {
+                    let width = groups.widths.entry(group).or_default();
+                    *width = (*width).max(items.len());
+                    groups
+                        .syms
+                        .insert(group, items.iter().map(keysym).collect());
+                }
+                (Some("actions"), ExprKind::List(items)) => {
+                    let width = groups.widths.entry(group).or_default();
+                    *width = (*width).max(items.len());
+                    let actions = items
+                        .iter()
+                        .map(|item| match &item.kind {
+                            ExprKind::Braced(actions) => actions.clone(),
+                            _ => vec![item.clone()],
+                        })
+                        .collect();
+                    groups.actions.insert(group, actions);
+                }
+                _ => {}
+            }
+        }
+        groups
+    }
+
+    /// Get the automatic type of a group, ignoring explicit types, or `None`
+    /// if it has a level with several keysyms.
+    fn implicit_type(&self, group: LayoutIndex) -> Option<&'static str> {
+        let syms = self
+            .syms
+            .get(&group)?
+            .iter()
+            .copied()
+            .collect::<Option<Vec<Keysym>>>()?;
+        let width = self.widths.get(&group).copied().unwrap_or_default();
+        Some(automatic_type(&syms, width))
+    }
+
+    fn type_name(&self, group: LayoutIndex) -> String {
+        if let Some(name) = self.types.get(&group).or(self.default_type.as_ref()) {
+            return name.clone();
+        }
+        let syms: Vec<Keysym> = self
+            .syms
+            .get(&group)
+            .map(|syms| {
+                syms.iter()
+                    .map(|sym| sym.unwrap_or(Keysym::new(KEY_NoSymbol)))
+                    .collect()
+            })
+            .unwrap_or_default();
+        let width = self.widths.get(&group).copied().unwrap_or_default();
+        automatic_type(&syms, width).to_owned()
+    }
+}
+
+/// Condition of an interpret on the modifiers of a key.
+#[derive(Clone, Copy)]
+enum Predicate {
+    NoneOf,
+    AnyOfOrNone,
+    AnyOf,
+    AllOf,
+    Exactly,
+}
+
+/// Mask of the real modifiers.
+const REAL_MODS: ModMask = 0xff;
+
+/// An interpret of the compat section, which binds an action to the keys
+/// producing a keysym.
+struct Interpret {
+    /// Keysym matched, or `None` for any keysym.
+    keysym: Option<Keysym>,
+    predicate: Predicate,
+    modifiers: ModMask,
+    /// Whether the modifiers of the key only apply to the first level.
+    level_one_only: bool,
+    action: Option<Expr>,
+}
+
+impl Interpret {
+    fn new(
+        keymap: &Keymap,
+        sym: &Expr,
+        predicate: Option<&Expr>,
+        body: &[VarDef],
+        level_one_only: bool,
+    ) -> Interpret {
+        let (predicate, modifiers) = match predicate.map(|expr| &expr.kind) {
+            None => (Predicate::AnyOfOrNone, REAL_MODS),
+            Some(ExprKind::Ident(name)) if name.eq_ignore_ascii_case("any") => {
+                (Predicate::AnyOf, REAL_MODS)
+            }
+            Some(ExprKind::Call { name, args }) => {
+                let predicate = match name.to_ascii_lowercase().as_str() {
+                    "noneof" => Predicate::NoneOf,
+                    "anyofornone" => Predicate::AnyOfOrNone,
+                    "anyof" => Predicate::AnyOf,
+                    "allof" => Predicate::AllOf,
+                    _ => Predicate::Exactly,
+                };
+                let mods = args.first().map_or(0, |arg| mod_mask(keymap, &arg.value));
+                (predicate, mods & REAL_MODS)
+            }
+            Some(_) => (
+                Predicate::Exactly,
+                predicate.map_or(0, |expr| mod_mask(keymap, expr)) & REAL_MODS,
+            ),
+        };
+        let mut interpret = Interpret {
+            keysym: keysym(sym).filter(|sym| sym.raw() != KEY_NoSymbol),
+            predicate,
+            modifiers,
+            level_one_only,
+            action: None,
+        };
+        for var in body {
+            match var.field().map(str::to_ascii_lowercase).as_deref() {
+                Some("action") => interpret.action = Some(var.value.clone()),
+                Some("usemodmapmods" | "usemodmap") => {
+                    interpret.level_one_only = is_level_one(&var.value);
+                }
+                _ => {}
+            }
+        }
+        interpret
+    }
+
+    /// Whether the interpret applies to a level producing `syms`, of a key
+    /// mapped to the real modifiers `modmap`.
+    fn matches(&self, syms: &[Keysym], level: LevelIndex, modmap: ModMask) -> bool {
+        match (self.keysym, syms) {
+            (_, []) => return false,
+            (None, _) => {}
+            (Some(keysym), &[sym]) if keysym == sym => {}
+            _ => return false,
+        }
+        let mods = if self.level_one_only && level != 0 {
+            0
+        } else {
+            modmap
+        };
+        let m = self.modifiers;
+        match self.predicate {
+            Predicate::NoneOf => m & mods == 0,
+            Predicate::AnyOfOrNone => mods == 0 || m & mods != 0,
+            Predicate::AnyOf => m & mods != 0,
+            Predicate::AllOf => m & mods == m,
+            Predicate::Exactly => m == mods,
+        }
+    }
+}
+
+/// Whether a `useModMapMods` value restricts the modifiers to the first
+/// level.
+fn is_level_one(expr: &Expr) -> bool {
+    expr.as_ident().is_some_and(|value| {
+        value.eq_ignore_ascii_case("level1") || value.eq_ignore_ascii_case("levelone")
+    })
+}
+
+/// Convert the actions of a level, without `NoAction()`.
+fn actions(keymap: &Keymap, exprs: &[Expr], modmap: ModMask) -> Vec<Action> {
+    exprs
+        .iter()
+        .map(|expr| Action::from_expr(keymap, expr, modmap))
+        .filter(|action| *action != Action::NoAction)
+        .collect()
+}
+
+/// Get the type libxkbcommon assigns to a group of a key without explicit
+/// type, from the body of its `key` statement.
+pub(crate) fn implicit_key_type(body: &[VarDef], group: LayoutIndex) -> Option<&'static str> {
+    KeyGroups::new(body).implicit_type(group)
+}
+
+impl KeymapInfo {
+    pub(crate) fn new(keymap: &Keymap) -> KeymapInfo {
+        KeymapInfo::from_text(keymap, &keymap.get_as_string(KEYMAP_FORMAT_TEXT_V1))
+    }
+
+    /// Derive the information of a keymap from its text.
+    fn from_text(keymap: &Keymap, text: &str) -> KeymapInfo {
+        let file = match text::parse(text) {
+            Ok(file) => file,
+            Err(error) => {
+                return KeymapInfo {
+                    error: Some(error),
+                    ..KeymapInfo::default()
+                }
+            }
+        };
+        let mut info = KeymapInfo::default();
+        let statements = |kind| {
+            file.sections
+                .iter()
+                .filter(move |section| section.kind == kind)
+                .flat_map(|section| &section.statements)
+        };
+        for statement in statements(SectionKind::Types) {
+            if let StatementKind::KeyType { name, body } = &statement.kind {
+                info.types.push(key_type(keymap, name, body));
+            }
+        }
+        let mut interprets = Vec::new();
+        let mut level_one_only = false;
+        for statement in statements(SectionKind::Compat) {
+            match &statement.kind {
+                StatementKind::Var(var)
+                    if var
+                        .field()
+                        .is_some_and(|f| f.eq_ignore_ascii_case("useModMapMods")) =>
+                {
+                    level_one_only = is_level_one(&var.value);
+                }
+                StatementKind::Interpret {
+                    keysym,
+                    predicate,
+                    body,
+                } => interprets.push(Interpret::new(
+                    keymap,
+                    keysym,
+                    predicate.as_ref(),
+                    body,
+                    level_one_only,
+                )),
+                _ => {}
+            }
+        }
+        for statement in statements(SectionKind::Symbols) {
+            let StatementKind::ModifierMap { modifier, keys } = &statement.kind else {
+                continue;
+            };
+            let idx = keymap.mod_get_index(modifier.as_str());
+            if idx >= ModMask::BITS {
+                continue;
+            }
+            for key in keys {
+                if let ExprKind::KeyName(name) = &key.kind {
+                    if let Some(key) = keymap.key_by_name(name.as_str()) {
+                        *info.modmaps.entry(key).or_default() |= 1 << idx;
+                    }
+                }
+            }
+        }
+        for statement in statements(SectionKind::Symbols) {
+            let StatementKind::Key { name, body } = &statement.kind else {
+                continue;
+            };
+            let Some(key) = keymap.key_by_name(name.as_str()) else {
+                continue;
+            };
+            let groups = KeyGroups::new(body);
+            let types = (0..keymap.num_layouts_for_key(key))
+                .map(|layout| {
+                    let num_levels = keymap.num_levels_for_key(key, layout);
+                    let name = groups.type_name(layout);
+                    info.types
+                        .iter()
+                        .position(|t| t.name == name && t.num_levels == num_levels)
+                })
+                .collect();
+            info.key_types.insert(key, types);
+            let modmap = info.modmaps.get(&key).copied().unwrap_or(0);
+            let mut used = Vec::new();
+            let actions = (0..keymap.num_layouts_for_key(key))
+                .map(|layout| {
+                    let num_levels = keymap.num_levels_for_key(key, layout);
+                    if !groups.actions.is_empty() {
+                        let levels = groups.actions.get(&layout);
+                        return (0..num_levels as usize)
+                            .map(|level| {
+                                levels
+                                    .and_then(|levels| levels.get(level))
+                                    .map(|exprs| actions(keymap, exprs, modmap))
+                                    .unwrap_or_default()
+                            })
+                            .collect();
+                    }
+                    (0..num_levels)
+                        .map(|level| {
+                            let syms = keymap.key_get_syms_by_level(key, layout, level);
+                            let Some(idx) = interprets
+                                .iter()
+                                .position(|interpret| interpret.matches(syms, level, modmap))
+                            else {
+                                return Vec::new();
+                            };
+                            used.push(idx);
+                            interprets[idx]
+                                .action
+                                .as_ref()
+                                .map(|expr| actions(keymap, std::slice::from_ref(expr), modmap))
+                                .unwrap_or_default()
+                        })
+                        .collect()
+                })
+                .collect();
+            info.key_actions.insert(key, actions);
+            used.sort_unstable();
+            used.dedup();
+            info.key_interprets.insert(key, used);
+        }
+        info
+    }
+}
+
+impl KeymapInfo {
+    /// Get the indices of the interprets applied to a key, in the order of
+    /// the compat sections.
+    pub(crate) fn key_interprets(&self, key: Keycode) -> &[usize] {
+        self.key_interprets.get(&key).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Keymap {
+    pub(crate) fn info(&self) -> &KeymapInfo {
+        self.cache.info.get_or_init(|| KeymapInfo::new(self))
+    }
+
+    /// Get the error parsing the text of the keymap, or `None` if it was
+    /// parsed.
+    ///
+    /// The key types, modifier maps and actions of the keymap are derived
+    /// from its text: if it cannot be parsed, they are empty.
+    #[must_use]
+    pub fn introspect_error(&self) -> Option<&ParseError> {
+        self.info().error.as_ref()
+    }
+
+    /// Get the key types of the keymap.
+    #[must_use]
+    pub fn key_types(&self) -> &[KeyType] {
+        &self.info().types
+    }
+
+    /// Get the key type of a key in a given layout.
+    ///
+    /// Returns `None` if the key does not exist, does not have the layout,
+    /// or its type cannot be found in the keymap text.
+    #[must_use]
+    pub fn key_get_type(&self, key: Keycode, layout: LayoutIndex) -> Option<&KeyType> {
+        let info = self.info();
+        let idx = (*info.key_types.get(&key)?.get(layout as usize)?)?;
+        info.types.get(idx)
+    }
+
+    /// Get the name of a shift level of a key in a given layout, e.g.
+    /// `"Shift"` or `"AltGr"`.
+    ///
+    /// Returns `None` if the key, layout or level does not exist, or the
+    /// level has no name.
+    #[must_use]
+    pub fn key_get_level_name(
+        &self,
+        key: Keycode,
+        layout: LayoutIndex,
+        level: LevelIndex,
+    ) -> Option<&str> {
+        self.key_get_type(key, layout)?.level_name(level)
+    }
+
+    /// Get the modifiers a key is mapped to, i.e. the modifiers its
+    /// `SetMods()`, `LatchMods()` or `LockMods()` actions with
+    /// `modifiers=modMapMods` act on, as a mask of modifier indices.
+    ///
+    /// Returns 0 if the key does not exist or is not mapped to modifiers.
+    #[must_use]
+    pub fn key_get_mod_map(&self, key: Keycode) -> ModMask {
+        self.info().modmaps.get(&key).copied().unwrap_or(0)
+    }
+
+    /// Get the actions of a shift level of a key in a given layout.
+    ///
+    /// The actions are either given explicitly to the key, or bound by the
+    /// interprets of the keymap to the keysyms of the level. Returns an
+    /// empty slice if the key, layout or level does not exist, or the level
+    /// has no action.
+    #[must_use]
+    pub fn key_get_actions_by_level(
+        &self,
+        key: Keycode,
+        layout: LayoutIndex,
+        level: LevelIndex,
+    ) -> &[Action] {
+        self.info()
+            .key_actions
+            .get(&key)
+            .and_then(|layouts| layouts.get(layout as usize))
+            .and_then(|levels| levels.get(level as usize))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Get the keys with an action matching a predicate in any layout or
+    /// level, in ascending order, e.g. the keys terminating the server.
+    pub fn keys_with_action<P: FnMut(&Action) -> bool>(&self, mut predicate: P) -> Vec<Keycode> {
+        let mut keys: Vec<Keycode> = self
+            .info()
+            .key_actions
+            .iter()
+            .filter(|(_, layouts)| layouts.iter().flatten().flatten().any(&mut predicate))
+            .map(|(&key, _)| key)
+            .collect();
+        keys.sort_unstable();
+        keys
+    }
+}
+
+#[test]
+fn key_types_and_level_names() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(0);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let a = keymap.key_by_name("AC01").unwrap();
+    assert_eq!(keymap.key_get_type(a, 0).unwrap().name, "ALPHABETIC");
+    assert_eq!(keymap.key_get_level_name(a, 0, 1), Some("Caps"));
+    let minus = keymap.key_by_name("AE11").unwrap();
+    let key_type = keymap.key_get_type(minus, 1).unwrap();
+    assert_eq!(key_type.name, "FOUR_LEVEL_PLUS_LOCK");
+    assert_eq!(key_type.num_levels, keymap.num_levels_for_key(minus, 1));
+    assert_eq!(key_type.level_name(2), Some("Alt Base"));
+    assert!(keymap.key_get_type(minus, 2).is_none());
+}
+
+#[test]
+fn key_actions() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS, MOD_NAME_CAPS};
+
+    let context = Context::new(0);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        Some("terminate:ctrl_alt_bksp,grp:alt_shift_toggle".into()),
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let caps = keymap.key_by_name("CAPS").unwrap();
+    assert_eq!(
+        keymap.key_get_actions_by_level(caps, 0, 0),
+        [Action::LockMods {
+            modifiers: 1 << keymap.mod_get_index(MOD_NAME_CAPS),
+            affect: super::LockAffect::Both,
+        }]
+    );
+    let bksp = keymap.key_by_name("BKSP").unwrap();
+    assert_eq!(
+        keymap.keys_with_action(|action| *action == Action::Terminate),
+        [bksp]
+    );
+    let lalt = keymap.key_by_name("LALT").unwrap();
+    assert!(keymap
+        .key_get_actions_by_level(lalt, 0, 1)
+        .iter()
+        .any(|action| matches!(action, Action::LockGroup { .. })));
+    assert!(keymap
+        .key_get_actions_by_level(keymap.key_by_name("AC01").unwrap(), 0, 0)
+        .is_empty());
+}
+
+#[test]
+fn introspect_error() {
+    use super::{Context, State, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let names = || {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            "us",
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let keymap = names();
+    assert!(keymap.introspect_error().is_none());
+    // The handles on the keymap of a state share its info.
+    let state = State::new(&keymap);
+    assert!(std::ptr::eq(keymap.info(), state.get_keymap().info()));
+    // Another keymap, given a text which does not parse.
+    let broken = names();
+    let info = KeymapInfo::from_text(&broken, "xkb_keymap {");
+    assert!(broken.cache.info.set(info).is_ok());
+    assert!(broken.introspect_error().is_some());
+    assert!(broken.key_types().is_empty());
+}
@@ -0,0 +1,284 @@
+//! Human-readable labels for keysyms and modifiers.
+//!
+//! `keysym_get_name()` and `Keymap::mod_get_name()` return the X names of
+//! keysyms and modifiers (`BackSpace`, `Prior`, `Mod4`...), which are not
+//! meant to be shown to users. The functions in this module map them to
+//! labels suitable for display in shortcut editors, on-screen keyboards,
+//! key press visualizers and the like.
+
+use super::keysyms::*;
+use super::{
+    keysym_get_name, Keysym, VMOD_NAME_ALT, VMOD_NAME_HYPER, VMOD_NAME_LEVEL3, VMOD_NAME_LEVEL5,
+    VMOD_NAME_META, VMOD_NAME_NUM, VMOD_NAME_SCROLL, VMOD_NAME_SUPER,
+};
+
+/// The virtual modifiers `Keymap::mod_get_label()` labels `Mod1` to `Mod5`
+/// after, by order of preference.
+pub(crate) const LABELED_VMODS: [&str; 9] = [
+    VMOD_NAME_ALT,
+    VMOD_NAME_SUPER,
+    VMOD_NAME_LEVEL3,
+    "AltGr",
+    VMOD_NAME_NUM,
+    VMOD_NAME_LEVEL5,
+    VMOD_NAME_META,
+    VMOD_NAME_HYPER,
+    VMOD_NAME_SCROLL,
+];
+
+/// The style of a display label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LabelStyle {
+    /// A compact symbol, such as `⌫`, `⇧` or `⏎`.
+    ///
+    /// Keys without a well established symbol use their short label.
+    Symbolic,
+    /// A short English label, such as `Backspace`, `PgUp` or `AltGr`.
+    Short,
+    /// A long English label, such as `Backspace`, `Page Up` or `Alt Graph`.
+    Long,
+}
+
+/// Labels of a key or modifier, in the order (symbolic, short, long).
+type Labels = (&'static str, &'static str, &'static str);
+
+fn pick(labels: Labels, style: LabelStyle) -> &'static str {
+    match style {
+        LabelStyle::Symbolic => labels.0,
+        LabelStyle::Short => labels.1,
+        LabelStyle::Long => labels.2,
+    }
+}
+
+#[allow(non_upper_case_globals)]
+fn keysym_labels(keysym: u32) -> Option<Labels> {
+    Some(match keysym {
+        KEY_BackSpace => ("⌫", "Backspace", "Backspace"),
+        KEY_Tab => ("⇥", "Tab", "Tab"),
+        KEY_ISO_Left_Tab => ("⇤", "Tab", "Left Tab"),
+        KEY_Return => ("⏎", "Enter", "Return"),
+        KEY_KP_Enter => ("⌤", "Enter", "Keypad Enter"),
+        KEY_Escape => ("⎋", "Esc", "Escape"),
+        KEY_Delete => ("⌦", "Del", "Delete"),
+        KEY_KP_Delete => ("⌦", "Del", "Keypad Delete"),
+        KEY_Insert => ("⎀", "Ins", "Insert"),
+        KEY_KP_Insert => ("⎀", "Ins", "Keypad Insert"),
+        KEY_Home => ("⇱", "Home", "Home"),
+        KEY_KP_Home => ("⇱", "Home", "Keypad Home"),
+        KEY_End => ("⇲", "End", "End"),
+        KEY_KP_End => ("⇲", "End", "Keypad End"),
+        KEY_Prior => ("⇞", "PgUp", "Page Up"),
+        KEY_KP_Prior => ("⇞", "PgUp", "Keypad Page Up"),
+        KEY_Next => ("⇟", "PgDn", "Page Down"),
+        KEY_KP_Next => ("⇟", "PgDn", "Keypad Page Down"),
+        KEY_Left => ("←", "Left", "Left Arrow"),
+        KEY_KP_Left => ("←", "Left", "Keypad Left Arrow"),
+        KEY_Up => ("↑", "Up", "Up Arrow"),
+        KEY_KP_Up => ("↑", "Up", "Keypad Up Arrow"),
+        KEY_Right => ("→", "Right", "Right Arrow"),
+        KEY_KP_Right => ("→", "Right", "Keypad Right Arrow"),
+        KEY_Down => ("↓", "Down", "Down Arrow"),
+        KEY_KP_Down => ("↓", "Down", "Keypad Down Arrow"),
+        KEY_Begin | KEY_KP_Begin => ("⎶", "Begin", "Begin"),
+        KEY_space => ("␣", "Space", "Space"),
+        KEY_KP_Space => ("␣", "Space", "Keypad Space"),
+        KEY_Shift_L => ("⇧", "Shift", "Left Shift"),
+        KEY_Shift_R => ("⇧", "Shift", "Right Shift"),
+        KEY_Control_L => ("⌃", "Ctrl", "Left Control"),
+        KEY_Control_R => ("⌃", "Ctrl", "Right Control"),
+        KEY_Alt_L => ("⎇", "Alt", "Left Alt"),
+        KEY_Alt_R => ("⎇", "Alt", "Right Alt"),
+        KEY_Meta_L => ("◆", "Meta", "Left Meta"),
+        KEY_Meta_R => ("◆", "Meta", "Right Meta"),
+        KEY_Super_L => ("❖", "Super", "Left Super"),
+        KEY_Super_R => ("❖", "Super", "Right Super"),
+        KEY_Hyper_L => ("✦", "Hyper", "Left Hyper"),
+        KEY_Hyper_R => ("✦", "Hyper", "Right Hyper"),
+        KEY_Caps_Lock => ("⇪", "Caps", "Caps Lock"),
+        KEY_Shift_Lock => ("⇫", "ShiftLk", "Shift Lock"),
+        KEY_Num_Lock => ("⇭", "NumLk", "Num Lock"),
+        KEY_Scroll_Lock => ("⤓", "ScrLk", "Scroll Lock"),
+        KEY_ISO_Level3_Shift => ("⇮", "AltGr", "Alt Graph"),
+        KEY_ISO_Level3_Latch => ("⇮", "AltGr", "Alt Graph Latch"),
+        KEY_ISO_Level3_Lock => ("⇯", "AltGrLk", "Alt Graph Lock"),
+        KEY_ISO_Level5_Shift => ("Lvl5", "Lvl5", "Level 5 Shift"),
+        KEY_ISO_Level5_Latch => ("Lvl5", "Lvl5", "Level 5 Latch"),
+        KEY_ISO_Level5_Lock => ("Lvl5Lk", "Lvl5Lk", "Level 5 Lock"),
+        KEY_Mode_switch => ("⇮", "Mode", "Mode Switch"),
+        KEY_ISO_Next_Group => ("⇨", "Layout", "Next Layout"),
+        KEY_ISO_Prev_Group => ("⇦", "Layout", "Previous Layout"),
+        KEY_ISO_First_Group => ("⇤", "Layout", "First Layout"),
+        KEY_ISO_Last_Group => ("⇥", "Layout", "Last Layout"),
+        KEY_Multi_key => ("⎄", "Compose", "Compose"),
+        KEY_Menu => ("▤", "Menu", "Menu"),
+        KEY_Print => ("⎙", "PrtSc", "Print Screen"),
+        KEY_Sys_Req => ("SysRq", "SysRq", "System Request"),
+        KEY_Pause => ("⎉", "Pause", "Pause"),
+        KEY_Break => ("⎊", "Break", "Break"),
+        KEY_Help => ("⍰", "Help", "Help"),
+        KEY_Clear => ("⌧", "Clear", "Clear"),
+        KEY_Undo => ("⎌", "Undo", "Undo"),
+        KEY_Redo => ("↷", "Redo", "Redo"),
+        KEY_Find => ("⌕", "Find", "Find"),
+        KEY_Cancel => ("⎋", "Cancel", "Cancel"),
+        KEY_XF86AudioRaiseVolume => ("🔊", "Vol+", "Volume Up"),
+        KEY_XF86AudioLowerVolume => ("🔉", "Vol-", "Volume Down"),
+        KEY_XF86AudioMute => ("🔇", "Mute", "Mute"),
+        KEY_XF86AudioMicMute => ("🎙", "MicMute", "Microphone Mute"),
+        KEY_XF86AudioPlay => ("⏯", "Play", "Play"),
+        KEY_XF86AudioPause => ("⏸", "Pause", "Pause"),
+        KEY_XF86AudioStop => ("⏹", "Stop", "Stop"),
+        KEY_XF86AudioPrev => ("⏮", "Prev", "Previous Track"),
+        KEY_XF86AudioNext => ("⏭", "Next", "Next Track"),
+        KEY_XF86MonBrightnessUp => ("🔆", "Bright+", "Brightness Up"),
+        KEY_XF86MonBrightnessDown => ("🔅", "Bright-", "Brightness Down"),
+        KEY_XF86PowerOff => ("⏻", "Power", "Power Off"),
+        KEY_XF86Sleep => ("⏾", "Sleep", "Sleep"),
+        KEY_XF86Eject => ("⏏", "Eject", "Eject"),
+        _ => return None,
+    })
+}
+
+fn mod_labels(name: &str) -> Option<Labels> {
+    Some(match name {
+        "Shift" => ("⇧", "Shift", "Shift"),
+        "Lock" => ("⇪", "Caps", "Caps Lock"),
+        "Control" => ("⌃", "Ctrl", "Control"),
+        "Mod1" | "Alt" => ("⎇", "Alt", "Alt"),
+        "LAlt" => ("⎇", "Alt", "Left Alt"),
+        "RAlt" => ("⎇", "Alt", "Right Alt"),
+        "LControl" => ("⌃", "Ctrl", "Left Control"),
+        "RControl" => ("⌃", "Ctrl", "Right Control"),
+        "Mod2" | "NumLock" => ("⇭", "NumLk", "Num Lock"),
+        "Mod4" | "Super" => ("❖", "Super", "Super"),
+        "Mod5" | "LevelThree" | "AltGr" => ("⇮", "AltGr", "Alt Graph"),
+        "LevelFive" => ("Lvl5", "Lvl5", "Level 5"),
+        "Meta" => ("◆", "Meta", "Meta"),
+        "Hyper" => ("✦", "Hyper", "Hyper"),
+        "ScrollLock" => ("⤓", "ScrLk", "Scroll Lock"),
+        _ => return None,
+    })
+}
+
+/// Get a human-readable label for a keysym.
+///
+/// Function keys, modifiers, navigation and multimedia keys get a
+/// dedicated label in the requested style. Keysyms which produce a
+/// printable character are labeled with that character, uppercased
+/// for letters (as engraved on most keyboards). Keypad keys are
+/// additionally marked as such in the short and long styles.
+///
+/// Keysyms not known to this function fall back to `keysym_get_name()`.
+#[must_use]
+pub fn keysym_display_label(keysym: Keysym, style: LabelStyle) -> String {
+    if let Some(labels) = keysym_labels(keysym.raw()) {
+        return pick(labels, style).to_owned();
+    }
+    if (KEY_F1..=KEY_F35).contains(&keysym.raw()) {
+        return format!("F{}", keysym.raw() - KEY_F1 + 1);
+    }
+    if let Some(c) = keysym.key_char().filter(|c| !c.is_control()) {
+        let mut upper = c.to_uppercase();
+        let label = match (upper.next(), upper.next()) {
+            (Some(u), None) => u.to_string(),
+            _ => c.to_string(),
+        };
+        if keysym.is_keypad_key() {
+            return match style {
+                LabelStyle::Symbolic => label,
+                LabelStyle::Short => format!("Num {label}"),
+                LabelStyle::Long => format!("Keypad {label}"),
+            };
+        }
+        return label;
+    }
+    keysym_get_name(keysym)
+}
+
+/// Get a human-readable label for a modifier, given its name.
+///
+/// Both real modifiers (as in `MOD_NAME_*`) and the common virtual
+/// modifiers (as in `VMOD_NAME_*`) are recognized. The real modifiers
+/// are labeled after the virtual modifier they are conventionally bound
+/// to, e.g. `Mod4` is labeled `Super`.
+///
+/// Unknown modifiers are labeled with their name.
+#[must_use]
+pub fn mod_display_label(name: &str, style: LabelStyle) -> String {
+    mod_labels(name).map_or_else(|| name.to_owned(), |labels| pick(labels, style).to_owned())
+}
+
+/// Extension trait to get display labels directly from a `Keysym`.
+pub trait KeysymLabel {
+    /// Get a human-readable label for this keysym.
+    ///
+    /// See `keysym_display_label()`.
+    fn display_label(self, style: LabelStyle) -> String;
+}
+
+impl KeysymLabel for Keysym {
+    fn display_label(self, style: LabelStyle) -> String {
+        keysym_display_label(self, style)
+    }
+}
+
+#[test]
+fn keysym_display_labels() {
+    let label = |keysym, style| keysym_display_label(Keysym::new(keysym), style);
+    assert_eq!(label(KEY_a, LabelStyle::Short), "A");
+    assert_eq!(label(KEY_ssharp, LabelStyle::Short), "ß");
+    assert_eq!(label(KEY_F1, LabelStyle::Symbolic), "F1");
+    assert_eq!(label(KEY_F12, LabelStyle::Long), "F12");
+    assert_eq!(label(KEY_KP_7, LabelStyle::Long), "Keypad 7");
+    assert_eq!(label(KEY_Prior, LabelStyle::Symbolic), "⇞");
+    assert_eq!(label(KEY_Prior, LabelStyle::Long), "Page Up");
+    assert_eq!(label(KEY_dead_acute, LabelStyle::Short), "dead_acute");
+}
+
+#[test]
+fn mod_labels_follow_keymap() {
+    use super::{Context, Keymap, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+    use super::{KEYMAP_FORMAT_TEXT_V1, MOD_NAME_MOD4, MOD_NAME_MOD5};
+
+    assert_eq!(mod_display_label("Mod5", LabelStyle::Short), "AltGr");
+    assert_eq!(mod_display_label("Foo", LabelStyle::Long), "Foo");
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let us = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let label =
+        |keymap: &Keymap, name| keymap.mod_get_label(keymap.mod_get_index(name), LabelStyle::Short);
+    assert_eq!(label(&us, "Control"), "Ctrl");
+    assert_eq!(label(&us, "Mod1"), "Alt");
+    assert_eq!(label(&us, MOD_NAME_MOD4), "Super");
+    assert_eq!(label(&us, MOD_NAME_MOD5), "AltGr");
+    assert_eq!(us.mod_get_label(super::MOD_INVALID, LabelStyle::Short), "");
+
+    // AltGr mapped to Mod4, and nothing to Mod5.
+    let text = r#"xkb_keymap {
+        xkb_keycodes { <RALT> = 108; };
+        xkb_types { include "complete" };
+        xkb_compat { include "complete" };
+        xkb_symbols {
+            key <RALT> { [ ISO_Level3_Shift ] };
+            modifier_map Mod4 { <RALT> };
+        };
+    };"#;
+    let keymap = Keymap::new_from_string(
+        &context,
+        text.into(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    assert_eq!(label(&keymap, MOD_NAME_MOD4), "AltGr");
+    assert_eq!(label(&keymap, MOD_NAME_MOD5), "Mod5");
+}
@@ -0,0 +1,163 @@
+use super::{
+    Keymap, LedIndex, State, LED_NAME_CAPS, LED_NAME_COMPOSE, LED_NAME_KANA, LED_NAME_NUM,
+    LED_NAME_SCROLL,
+};
+
+/// Linux input LED codes (`LED_*` in `linux/input-event-codes.h`) of the
+/// keymap LED names.
+const INPUT_LEDS: [(&str, u16); 5] = [
+    (LED_NAME_NUM, 0x00),
+    (LED_NAME_CAPS, 0x01),
+    (LED_NAME_SCROLL, 0x02),
+    (LED_NAME_COMPOSE, 0x03),
+    (LED_NAME_KANA, 0x04),
+];
+
+/// Get the Linux input LED code of a keymap LED name, e.g. `LED_CAPSL` for
+/// `xkb::LED_NAME_CAPS`.
+///
+/// Returns `None` if the LED has no counterpart in the Linux input
+/// subsystem.
+#[must_use]
+pub fn led_name_to_input_code(name: &str) -> Option<u16> {
+    INPUT_LEDS
+        .iter()
+        .find(|&&(led, _)| led == name)
+        .map(|&(_, code)| code)
+}
+
+/// A write of a LED to a Linux input device, i.e. an `EV_LED` event with
+/// the given code, and a value of 1 if `on` or 0 otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LedWrite {
+    pub code: u16,
+    pub on: bool,
+}
+
+/// Synchronizes the LEDs of a keyboard state to a physical keyboard.
+///
+/// `LedSync` remembers the LEDs last written to the device, and produces the
+/// `EV_LED` writes needed to bring the device up to date with the state,
+/// e.g. after each call to `State::update_key()`. Only the LEDs of the
+/// keymap which have a Linux input code are synchronized.
+pub struct LedSync {
+    leds: Vec<(LedIndex, u16)>,
+    written: Option<Vec<bool>>,
+}
+
+impl LedSync {
+    /// Create a LED synchronizer for the LEDs of a keymap.
+    ///
+    /// The first update writes all the LEDs.
+    #[must_use]
+    pub fn new(keymap: &Keymap) -> LedSync {
+        let leds = (0..keymap.num_leds())
+            .filter_map(|idx| {
+                led_name_to_input_code(keymap.led_get_name(idx)).map(|code| (idx, code))
+            })
+            .collect();
+        LedSync {
+            leds,
+            written: None,
+        }
+    }
+
+    /// Forget the LEDs last written, so that the next update writes all the
+    /// LEDs, e.g. after the device was reopened.
+    pub fn reset(&mut self) {
+        self.written = None;
+    }
+
+    /// Get the LED writes needed to reflect the LEDs of a state.
+    ///
+    /// The writes are assumed to be applied to the device.
+    pub fn update(&mut self, state: &State) -> Vec<LedWrite> {
+        let leds: Vec<bool> = self
+            .leds
+            .iter()
+            .map(|&(idx, _)| state.led_index_is_active(idx))
+            .collect();
+        let writes = self
+            .leds
+            .iter()
+            .zip(&leds)
+            .enumerate()
+            .filter(|&(i, (_, &on))| match &self.written {
+                Some(written) => written[i] != on,
+                None => true,
+            })
+            .map(|(_, (&(_, code), &on))| LedWrite { code, on })
+            .collect();
+        self.written = Some(leds);
+        writes
+    }
+
+    /// Write the LEDs of a state to an evdev device.
+    ///
+    /// # Errors
+    /// Returns the error of the write to the device, in which case all the
+    /// LEDs are written again on the next update.
+    #[cfg(feature = "evdev")]
+    pub fn apply(&mut self, state: &State, device: &mut evdev::Device) -> std::io::Result<()> {
+        use evdev::{EventType, InputEvent};
+
+        let writes = self.update(state);
+        if writes.is_empty() {
+            return Ok(());
+        }
+        let mut events: Vec<InputEvent> = writes
+            .iter()
+            .map(|write| InputEvent::new(EventType::LED, write.code, i32::from(write.on)))
+            .collect();
+        events.push(InputEvent::new(EventType::SYNCHRONIZATION, 0, 0));
+        let result = device.send_events(&events);
+        if result.is_err() {
+            self.reset();
+        }
+        result
+    }
+}
+
+#[test]
+fn led_sync_writes_changes() {
+    use super::{Context, KeyDirection, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS};
+
+    assert_eq!(led_name_to_input_code(LED_NAME_CAPS), Some(0x01));
+    assert_eq!(led_name_to_input_code("Mail"), None);
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let caps = keymap.key_by_name("CAPS").unwrap();
+    let mut state = State::new(&keymap);
+    let mut sync = LedSync::new(&keymap);
+    let writes = sync.update(&state);
+    assert!(writes.iter().all(|write| !write.on));
+    for name in [LED_NAME_NUM, LED_NAME_CAPS, LED_NAME_SCROLL] {
+        let code = led_name_to_input_code(name).unwrap();
+        assert!(writes.iter().any(|write| write.code == code));
+    }
+    assert!(sync.update(&state).is_empty());
+
+    let caps_on = LedWrite {
+        code: 0x01,
+        on: true,
+    };
+    state.update_key(caps, KeyDirection::Down);
+    assert_eq!(sync.update(&state), [caps_on]);
+    state.update_key(caps, KeyDirection::Up);
+    assert!(sync.update(&state).is_empty());
+
+    sync.reset();
+    let writes = sync.update(&state);
+    assert_eq!(writes.len(), sync.leds.len());
+    assert!(writes.contains(&caps_on));
+}
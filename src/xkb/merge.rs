@@ -0,0 +1,588 @@
+//! Merging of layouts from several keymaps.
+//!
+//! `Keymap::merge_groups()` builds a keymap whose layouts are taken from
+//! other keymaps, e.g. a custom keymap file for one language and a layout
+//! compiled from RMLVO names. The keymaps are combined through their text:
+//! the keycodes, LED maps and compat of the first keymap are kept, and the
+//! keys, key types, interprets and virtual modifiers used by the other
+//! layouts are added to them. Definitions which would shadow each other,
+//! such as two interprets of the same keysym, are reported as conflicts.
+
+use super::canonical::name_groups;
+use super::editor::{expr, ident, is_field, layout_of, set_layout};
+use super::text::{
+    self, ExprKind, KeymapFile, ParseError, Section, SectionKind, Span, Statement, StatementKind,
+    VarDef,
+};
+use super::{
+    Context, Keycode, Keymap, LayoutIndex, ModMask, KEYMAP_COMPILE_NO_FLAGS, KEYMAP_FORMAT_TEXT_V1,
+};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// Maximum number of groups of a keymap. X11 clients cannot use more.
+const MAX_GROUPS: usize = 4;
+
+const REAL_MOD_NAMES: [&str; 8] = [
+    "Shift", "Lock", "Control", "Mod1", "Mod2", "Mod3", "Mod4", "Mod5",
+];
+
+/// Error of `Keymap::merge_groups()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeError {
+    /// Index of the source of the error, or `None` if it is not specific to
+    /// a source.
+    pub source: Option<usize>,
+    pub kind: MergeErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MergeErrorKind {
+    /// There are no sources, or more than four.
+    GroupCount,
+    /// The layout does not exist in the source keymap.
+    InvalidLayout(LayoutIndex),
+    /// The text of the source keymap could not be parsed.
+    Parse(ParseError),
+    /// The key of the source has the keycode of another key of the merged
+    /// keymap.
+    KeycodeConflict(String),
+    /// The virtual modifier is mapped to different real modifiers in the
+    /// source and in a previous source.
+    ModifierConflict(String),
+    /// The key is mapped to different modifiers in the source and in a
+    /// previous source.
+    ModMapConflict(String),
+    /// An interpret used by the source has the keysym and predicate of
+    /// another interpret of the merged keymap, but different actions. The
+    /// interpret is given as written, e.g. `Caps_Lock+AnyOfOrNone(all)`.
+    InterpretConflict(String),
+    /// libxkbcommon failed to compile the merged keymap.
+    Compile,
+}
+
+impl fmt::Display for MergeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(source) = self.source {
+            write!(f, "source {source}: ")?;
+        }
+        match &self.kind {
+            MergeErrorKind::GroupCount => {
+                write!(f, "a keymap has between 1 and {MAX_GROUPS} groups")
+            }
+            MergeErrorKind::InvalidLayout(layout) => write!(f, "invalid layout {layout}"),
+            MergeErrorKind::Parse(err) => write!(f, "failed to parse the keymap: {err}"),
+            MergeErrorKind::KeycodeConflict(name) => {
+                write!(f, "the keycode of key <{name}> is already used")
+            }
+            MergeErrorKind::ModifierConflict(name) => {
+                write!(f, "virtual modifier {name} is mapped to other modifiers")
+            }
+            MergeErrorKind::ModMapConflict(name) => {
+                write!(f, "key <{name}> is mapped to other modifiers")
+            }
+            MergeErrorKind::InterpretConflict(interpret) => {
+                write!(f, "interpret {interpret} is defined differently")
+            }
+            MergeErrorKind::Compile => write!(f, "the merged keymap failed to compile"),
+        }
+    }
+}
+
+impl Error for MergeError {}
+
+fn statement(kind: StatementKind) -> Statement {
+    Statement {
+        kind,
+        span: Span::default(),
+    }
+}
+
+fn section(file: &mut KeymapFile, kind: SectionKind) -> &mut Vec<Statement> {
+    let idx = match file.sections.iter().position(|s| s.kind == kind) {
+        Some(idx) => idx,
+        None => {
+            file.sections.push(Section {
+                flags: Vec::new(),
+                kind,
+                name: None,
+                statements: Vec::new(),
+                span: Span::default(),
+            });
+            file.sections.len() - 1
+        }
+    };
+    &mut file.sections[idx].statements
+}
+
+/// Get the keysym and predicate of an interpret, as written.
+fn interpret_match(statement: &Statement) -> Option<String> {
+    match &statement.kind {
+        StatementKind::Interpret {
+            keysym,
+            predicate: Some(predicate),
+            ..
+        } => Some(format!("{keysym}+{predicate}")),
+        StatementKind::Interpret { keysym, .. } => Some(keysym.to_string()),
+        _ => None,
+    }
+}
+
+fn statements(file: &KeymapFile, kind: SectionKind) -> impl Iterator<Item = &Statement> {
+    file.sections
+        .iter()
+        .filter(move |s| s.kind == kind)
+        .flat_map(|s| &s.statements)
+}
+
+/// The merged keymap being built.
+struct Merge<'a> {
+    base: &'a Keymap,
+    file: KeymapFile,
+    /// Keycodes by key name, and the keycodes in use.
+    keycodes: HashMap<String, i64>,
+    used: HashSet<i64>,
+    /// Key types by name, as written.
+    types: HashMap<String, String>,
+    /// Interprets by keysym and predicate, as written.
+    interprets: HashMap<String, String>,
+    /// Virtual modifiers and their mapping.
+    vmods: HashMap<String, ModMask>,
+    /// Key bodies, in order of appearance.
+    keys: Vec<(String, Vec<VarDef>)>,
+    modmaps: HashMap<String, ModMask>,
+}
+
+impl<'a> Merge<'a> {
+    fn new(base: &'a Keymap, mut file: KeymapFile) -> Merge<'a> {
+        let mut keycodes = HashMap::new();
+        for statement in statements(&file, SectionKind::Keycodes) {
+            if let StatementKind::Keycode { name, value } = &statement.kind {
+                if let Some(value) = value.as_integer() {
+                    keycodes.insert(name.clone(), value);
+                }
+            }
+        }
+        let types = statements(&file, SectionKind::Types)
+            .filter_map(|s| match &s.kind {
+                StatementKind::KeyType { name, .. } => Some((name.clone(), s.to_string())),
+                _ => None,
+            })
+            .collect();
+        let interprets = statements(&file, SectionKind::Compat)
+            .filter_map(|s| Some((interpret_match(s)?, s.to_string())))
+            .collect();
+        // The keys and modifier map are rebuilt from the sources.
+        section(&mut file, SectionKind::Symbols).retain(|s| match &s.kind {
+            StatementKind::Key { .. } | StatementKind::ModifierMap { .. } => false,
+            StatementKind::Var(var) => !(is_field(var, "name") && var.index().is_some()),
+            _ => true,
+        });
+        Merge {
+            base,
+            file,
+            used: keycodes.values().copied().collect(),
+            keycodes,
+            types,
+            interprets,
+            vmods: HashMap::new(),
+            keys: Vec::new(),
+            modmaps: HashMap::new(),
+        }
+    }
+
+    /// Add the virtual modifiers of a source, checking their mapping.
+    fn add_vmods(&mut self, keymap: &Keymap) -> Result<(), MergeErrorKind> {
+        let mut names = Vec::new();
+        for (idx, name) in keymap.mods().enumerate().skip(REAL_MOD_NAMES.len()) {
+            let mask = keymap.mod_get_mask(idx as u32);
+            match self.vmods.get(name) {
+                Some(&other) if mask != 0 && other != 0 && mask != other => {
+                    return Err(MergeErrorKind::ModifierConflict(name.to_owned()));
+                }
+                Some(&other) if other != 0 => {}
+                Some(_) => {
+                    self.vmods.insert(name.to_owned(), mask);
+                }
+                None => {
+                    self.vmods.insert(name.to_owned(), mask);
+                    if self.base.mod_get_index(name) == super::MOD_INVALID {
+                        names.push(VarDef {
+                            name: None,
+                            value: ident(name),
+                            span: Span::default(),
+                        });
+                    }
+                }
+            }
+        }
+        if !names.is_empty() {
+            section(&mut self.file, SectionKind::Types)
+                .insert(0, statement(StatementKind::VirtualModifiers(names)));
+        }
+        Ok(())
+    }
+
+    /// Get the name of a key of a source in the merged keymap.
+    fn key_name(&mut self, keymap: &Keymap, key: Keycode) -> Result<String, MergeErrorKind> {
+        let name = keymap.key_get_name(key).unwrap_or_default();
+        if let Some(name) = self
+            .base
+            .key_by_name(name)
+            .and_then(|key| self.base.key_get_name(key))
+        {
+            return Ok(name.to_owned());
+        }
+        if self.keycodes.contains_key(name) {
+            return Ok(name.to_owned());
+        }
+        let value = i64::from(key.raw());
+        if !self.used.insert(value) {
+            return Err(MergeErrorKind::KeycodeConflict(name.to_owned()));
+        }
+        self.keycodes.insert(name.to_owned(), value);
+        let keycodes = section(&mut self.file, SectionKind::Keycodes);
+        for statement in keycodes.iter_mut() {
+            let StatementKind::Var(var) = &mut statement.kind else {
+                continue;
+            };
+            let Some(bound) = var.value.as_integer() else {
+                continue;
+            };
+            let bound = match var.field() {
+                Some(field) if field.eq_ignore_ascii_case("minimum") => bound.min(value),
+                Some(field) if field.eq_ignore_ascii_case("maximum") => bound.max(value),
+                _ => continue,
+            };
+            var.value.kind = ExprKind::Integer {
+                value: bound,
+                hex: false,
+            };
+        }
+        keycodes.push(statement(StatementKind::Keycode {
+            name: name.to_owned(),
+            value: expr(ExprKind::Integer { value, hex: false }),
+        }));
+        Ok(name.to_owned())
+    }
+
+    /// Add a key type of a source, renaming it if the merged keymap has a
+    /// different type of the same name.
+    fn add_type(&mut self, source: &KeymapFile, name: &str, group: usize) -> String {
+        let Some(mut statement) = statements(source, SectionKind::Types)
+            .find(|s| matches!(&s.kind, StatementKind::KeyType { name: n, .. } if n == name))
+            .cloned()
+        else {
+            return name.to_owned();
+        };
+        let text = statement.to_string();
+        match self.types.get(name) {
+            Some(other) if *other == text => return name.to_owned(),
+            Some(_) => {}
+            None => {
+                self.types.insert(name.to_owned(), text);
+                section(&mut self.file, SectionKind::Types).push(statement);
+                return name.to_owned();
+            }
+        }
+        let renamed = format!("{name}:{}", group + 1);
+        if let StatementKind::KeyType { name, .. } = &mut statement.kind {
+            name.clone_from(&renamed);
+        }
+        let text = statement.to_string();
+        if self.types.insert(renamed.clone(), text).is_none() {
+            section(&mut self.file, SectionKind::Types).push(statement);
+        }
+        renamed
+    }
+
+    /// Add a layout of a source as a group of the merged keymap.
+    fn add_group(
+        &mut self,
+        keymap: &Keymap,
+        source: &KeymapFile,
+        layout: LayoutIndex,
+        group: usize,
+    ) -> Result<(), MergeErrorKind> {
+        self.add_vmods(keymap)?;
+        section(&mut self.file, SectionKind::Symbols).push(statement(StatementKind::Var(VarDef {
+            name: Some(expr(ExprKind::ArrayRef {
+                elem: None,
+                field: "name".to_owned(),
+                index: Box::new(super::editor::group(group as LayoutIndex)),
+            })),
+            value: expr(ExprKind::String(keymap.layout_get_name(layout).to_owned())),
+            span: Span::default(),
+        })));
+
+        let compat: Vec<&Statement> = statements(source, SectionKind::Compat)
+            .filter(|s| matches!(s.kind, StatementKind::Interpret { .. }))
+            .collect();
+        for statement in statements(source, SectionKind::Symbols) {
+            let StatementKind::Key { name, body } = &statement.kind else {
+                continue;
+            };
+            let Some(key) = keymap.key_by_name(name.as_str()) else {
+                continue;
+            };
+            let num_layouts = keymap.num_layouts_for_key(key);
+            if num_layouts == 0 {
+                continue;
+            }
+            // Keys with fewer layouts wrap around, as in the source keymap.
+            let layout = layout % num_layouts;
+            let name = self.key_name(keymap, key)?;
+
+            let modmap = keymap.key_get_mod_map(key);
+            match self.modmaps.get(&name) {
+                Some(&other) if modmap != 0 && other != 0 && modmap != other => {
+                    return Err(MergeErrorKind::ModMapConflict(name));
+                }
+                Some(&other) if other != 0 => {}
+                _ => {
+                    self.modmaps.insert(name.clone(), modmap);
+                }
+            }
+            for &idx in keymap.info().key_interprets(key) {
+                let Some(&interpret) = compat.get(idx) else {
+                    continue;
+                };
+                let Some(head) = interpret_match(interpret) else {
+                    continue;
+                };
+                let text = interpret.to_string();
+                match self.interprets.get(&head) {
+                    Some(other) if *other != text => {
+                        return Err(MergeErrorKind::InterpretConflict(head));
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.interprets.insert(head, text);
+                        section(&mut self.file, SectionKind::Compat).push(interpret.clone());
+                    }
+                }
+            }
+
+            let mut body = body.clone();
+            name_groups(&mut body);
+            let mut vars: Vec<VarDef> = Vec::new();
+            if let Some(key_type) = keymap.key_get_type(key, layout) {
+                let key_type = self.add_type(source, &key_type.name, group);
+                vars.push(VarDef {
+                    name: Some(expr(ExprKind::ArrayRef {
+                        elem: None,
+                        field: "type".to_owned(),
+                        index: Box::new(super::editor::group(group as LayoutIndex)),
+                    })),
+                    value: expr(ExprKind::String(key_type)),
+                    span: Span::default(),
+                });
+            }
+            vars.extend(
+                body.iter()
+                    .filter(|var| layout_of(var) == Some(layout) && !is_field(var, "type"))
+                    .cloned()
+                    .map(|mut var| {
+                        set_layout(&mut var, group as LayoutIndex);
+                        var
+                    }),
+            );
+            match self.keys.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, merged)) => merged.extend(vars),
+                None => {
+                    // The fields of the key itself come from the first source
+                    // with the key.
+                    let mut merged: Vec<VarDef> = body
+                        .into_iter()
+                        .filter(|var| var.index().is_none() && !is_field(var, "type"))
+                        .collect();
+                    merged.extend(vars);
+                    self.keys.push((name, merged));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> String {
+        let symbols = section(&mut self.file, SectionKind::Symbols);
+        for (name, body) in self.keys.drain(..) {
+            symbols.push(statement(StatementKind::Key { name, body }));
+        }
+        for (idx, modifier) in REAL_MOD_NAMES.iter().enumerate() {
+            let mut keys: Vec<&String> = self
+                .modmaps
+                .iter()
+                .filter(|(_, &mask)| mask & (1 << idx) != 0)
+                .map(|(name, _)| name)
+                .collect();
+            if keys.is_empty() {
+                continue;
+            }
+            keys.sort_unstable();
+            symbols.push(statement(StatementKind::ModifierMap {
+                modifier: (*modifier).to_owned(),
+                keys: keys
+                    .into_iter()
+                    .map(|name| expr(ExprKind::KeyName(name.clone())))
+                    .collect(),
+            }));
+        }
+        self.file.to_string()
+    }
+}
+
+impl Keymap {
+    /// Create a keymap whose groups are layouts of other keymaps, in order.
+    ///
+    /// Keys are matched by name (`key_get_name()`). Keycodes, aliases, LEDs
+    /// and the fields of keys other than their symbols, actions and types
+    /// are taken from the first source which has them. Key types which
+    /// differ from a type of the same name in a previous source are renamed
+    /// with the group number, e.g. `"TWO_LEVEL:2"`.
+    ///
+    /// The keymap is compiled in `context`.
+    ///
+    /// # Errors
+    /// Returns an error if there are no sources or more than four, which is
+    /// the most XKB and X11 clients support, a layout does not exist, keys
+    /// or virtual modifiers are mapped to different modifiers by different
+    /// sources, a key of a source has the keycode of another key, or the
+    /// merged keymap fails to compile.
+    pub fn merge_groups(
+        context: &Context,
+        sources: &[(&Keymap, LayoutIndex)],
+    ) -> Result<Keymap, MergeError> {
+        if sources.is_empty() || sources.len() > MAX_GROUPS {
+            return Err(MergeError {
+                source: None,
+                kind: MergeErrorKind::GroupCount,
+            });
+        }
+        let mut files = Vec::with_capacity(sources.len());
+        for (idx, &(keymap, layout)) in sources.iter().enumerate() {
+            let error = |kind| MergeError {
+                source: Some(idx),
+                kind,
+            };
+            if layout >= keymap.num_layouts() {
+                return Err(error(MergeErrorKind::InvalidLayout(layout)));
+            }
+            files.push(text::parse_keymap(keymap).map_err(|e| error(MergeErrorKind::Parse(e)))?);
+        }
+
+        let mut merge = Merge::new(sources[0].0, files[0].clone());
+        for (idx, (&(keymap, layout), file)) in sources.iter().zip(&files).enumerate() {
+            merge
+                .add_group(keymap, file, layout, idx)
+                .map_err(|kind| MergeError {
+                    source: Some(idx),
+                    kind,
+                })?;
+        }
+        Keymap::new_from_string(
+            context,
+            merge.finish(),
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .ok_or(MergeError {
+            source: None,
+            kind: MergeErrorKind::Compile,
+        })
+    }
+}
+
+#[test]
+fn merged_groups() {
+    use super::CONTEXT_NO_FLAGS;
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let names = |layout: &str| {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layout,
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let us_de = names("us,de");
+    let custom = Keymap::new_from_string(
+        &context,
+        r#"xkb_keymap {
+            xkb_keycodes { <AC01> = 38; <I300> = 300; };
+            xkb_types {
+                virtual_modifiers LevelThree;
+                type "TWO_LEVEL" {
+                    modifiers = LevelThree;
+                    map[LevelThree] = Level2;
+                };
+            };
+            xkb_compat { };
+            xkb_symbols {
+                name[Group1] = "Custom";
+                key <AC01> { type = "TWO_LEVEL", [ Greek_alpha, Greek_ALPHA ] };
+                key <I300> { [ F13 ] };
+            };
+        };"#
+        .to_owned(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+
+    let merged = Keymap::merge_groups(&context, &[(&us_de, 1), (&custom, 0)]).unwrap();
+    assert_eq!(merged.num_layouts(), 2);
+    assert_eq!(merged.layout_get_name(0), "German");
+    assert_eq!(merged.layout_get_name(1), "Custom");
+    let ac01 = merged.key_by_name("AC01").unwrap();
+    assert_eq!(
+        merged.key_get_syms_by_level(ac01, 0, 1),
+        us_de.key_get_syms_by_level(ac01, 1, 1)
+    );
+    assert_eq!(
+        merged.key_get_syms_by_level(ac01, 1, 1),
+        custom.key_get_syms_by_level(ac01, 0, 1)
+    );
+    assert_eq!(merged.key_get_type(ac01, 1).unwrap().name, "TWO_LEVEL:2");
+    let lfsh = merged.key_by_name("LFSH").unwrap();
+    assert_eq!(merged.key_get_mod_map(lfsh), 1);
+    assert!(merged.key_by_name("I300").is_some());
+
+    let Err(error) = Keymap::merge_groups(&context, &[(&us_de, 0); 5]) else {
+        panic!("a keymap with five groups compiled");
+    };
+    assert_eq!(error.kind, MergeErrorKind::GroupCount);
+
+    // Caps Lock sets Control instead of locking Lock.
+    let custom = Keymap::new_from_string(
+        &context,
+        r#"xkb_keymap {
+            xkb_keycodes { <CAPS> = 66; };
+            xkb_types { include "basic" };
+            xkb_compat {
+                interpret Caps_Lock+AnyOfOrNone(all) {
+                    action = SetMods(modifiers = Control);
+                };
+            };
+            xkb_symbols { key <CAPS> { [ Caps_Lock ] }; };
+        };"#
+        .to_owned(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let Err(error) = Keymap::merge_groups(&context, &[(&us_de, 0), (&custom, 0)]) else {
+        panic!("conflicting interprets were merged");
+    };
+    assert_eq!(error.source, Some(1));
+    assert_eq!(
+        error.kind,
+        MergeErrorKind::InterpretConflict("Caps_Lock+AnyOfOrNone(all)".into())
+    );
+}
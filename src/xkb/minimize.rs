@@ -0,0 +1,309 @@
+//! Minimization of keymaps.
+//!
+//! The text of a keymap compiled from RMLVO names holds every key type and
+//! compat interpret of the included files, most of which no key uses, and
+//! every keycode of the keycodes file, most of which have no symbols.
+//! `Keymap::minimized()` removes them, so that the keymap sent to clients,
+//! e.g. by a Wayland compositor, is a fraction of the size.
+
+use super::canonical::{canonicalize, CANONICAL_OMIT_DEFAULTS};
+use super::text::{self, ExprKind, ParseError, SectionKind, StatementKind};
+use super::{
+    Context, Keycode, Keymap, LayoutIndex, LevelIndex, ModMask, KEYMAP_COMPILE_NO_FLAGS,
+    KEYMAP_FORMAT_TEXT_V1,
+};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+pub type MinimizeFlags = u32;
+
+pub const MINIMIZE_NO_FLAGS: u32 = 0;
+/// Check that every level of every key gives the same keysyms, modifier
+/// masks and actions in the minimized keymap, and that the keymap has the
+/// same modifiers, layouts and LEDs.
+///
+/// The minimized keymap is compared with the original keymap compiled again
+/// from its text, rather than with the original keymap itself: libxkbcommon
+/// does not write the type entries of the first level without preserved
+/// modifiers, so that their modifier masks are lost in any keymap text.
+pub const MINIMIZE_VERIFY: u32 = 1 << 0;
+
+/// Error of `Keymap::minimized()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MinimizeError {
+    /// The text of the keymap could not be parsed.
+    Parse(ParseError),
+    /// libxkbcommon failed to compile the minimized keymap.
+    Compile,
+    /// The modifiers, layouts or LEDs of the minimized keymap differ.
+    KeymapMismatch,
+    /// A level of a key differs in the minimized keymap. Differences of the
+    /// key itself, such as its repeat, are reported at layout and level 0.
+    KeyMismatch {
+        key: Keycode,
+        layout: LayoutIndex,
+        level: LevelIndex,
+    },
+}
+
+impl fmt::Display for MinimizeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MinimizeError::Parse(err) => write!(f, "failed to parse the keymap: {err}"),
+            MinimizeError::Compile => write!(f, "failed to compile the minimized keymap"),
+            MinimizeError::KeymapMismatch => {
+                write!(f, "the modifiers, layouts or LEDs of the keymap changed")
+            }
+            MinimizeError::KeyMismatch { key, layout, level } => write!(
+                f,
+                "key {} changed in layout {layout}, level {level}",
+                key.raw()
+            ),
+        }
+    }
+}
+
+impl Error for MinimizeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MinimizeError::Parse(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Get the modifier masks of a level of a key.
+fn level_masks(
+    keymap: &Keymap,
+    key: Keycode,
+    layout: LayoutIndex,
+    level: LevelIndex,
+) -> Vec<ModMask> {
+    let mut masks = [0; 64];
+    let count = keymap.key_get_mods_for_level(key, layout, level, &mut masks);
+    let mut masks = masks[..count].to_vec();
+    masks.sort_unstable();
+    masks
+}
+
+/// Check that a minimized keymap behaves as the original.
+fn verify(
+    old: &Keymap,
+    new: &Keymap,
+    keycodes: Option<&RangeInclusive<Keycode>>,
+) -> Result<(), MinimizeError> {
+    let same =
+        old.mods().eq(new.mods()) && old.layouts().eq(new.layouts()) && old.leds().eq(new.leds());
+    if !same {
+        return Err(MinimizeError::KeymapMismatch);
+    }
+    let mut result = Ok(());
+    old.key_for_each(|old, key| {
+        if result.is_err() || keycodes.is_some_and(|range| !range.contains(&key)) {
+            return;
+        }
+        let num_layouts = old.num_layouts_for_key(key);
+        let mismatch = |layout, level| MinimizeError::KeyMismatch { key, layout, level };
+        if num_layouts != new.num_layouts_for_key(key)
+            || (num_layouts > 0 && old.key_repeats(key) != new.key_repeats(key))
+        {
+            result = Err(mismatch(0, 0));
+            return;
+        }
+        for layout in 0..num_layouts {
+            let num_levels = old.num_levels_for_key(key, layout);
+            if num_levels != new.num_levels_for_key(key, layout) {
+                result = Err(mismatch(layout, 0));
+                return;
+            }
+            for level in 0..num_levels {
+                if level_masks(old, key, layout, level) != level_masks(new, key, layout, level)
+                    || old.key_get_syms_by_level(key, layout, level)
+                        != new.key_get_syms_by_level(key, layout, level)
+                    || old.key_get_actions_by_level(key, layout, level)
+                        != new.key_get_actions_by_level(key, layout, level)
+                {
+                    result = Err(mismatch(layout, level));
+                    return;
+                }
+            }
+        }
+    });
+    result
+}
+
+impl Keymap {
+    /// Create an equivalent keymap without the key types, interprets,
+    /// indicator maps, aliases and keycodes which are not used by the keys,
+    /// optionally restricted to a range of keycodes. The keys of the modifier
+    /// map are kept outside of the range, since they bind the virtual
+    /// modifiers.
+    ///
+    /// The keymap is compiled in `context`, and written in canonical form
+    /// (see `xkb::canonical`).
+    ///
+    /// # Errors
+    /// Returns an error if the keymap text cannot be parsed, the minimized
+    /// keymap fails to compile, or, with `MINIMIZE_VERIFY`, it differs from
+    /// this keymap in the keycode range.
+    pub fn minimized(
+        &self,
+        context: &Context,
+        keycodes: Option<RangeInclusive<Keycode>>,
+        flags: MinimizeFlags,
+    ) -> Result<Keymap, MinimizeError> {
+        let mut file = text::parse_keymap(self).map_err(MinimizeError::Parse)?;
+        let info = self.info();
+        let in_range = |key: Keycode| match &keycodes {
+            Some(range) => range.contains(&key),
+            None => true,
+        };
+
+        // Keys with symbols in the keycode range, and the keys of the
+        // modifier map, which bind the virtual modifiers of the keys in range.
+        let mut keys = HashSet::new();
+        let (mut min, mut max) = keycodes
+            .as_ref()
+            .map_or((0, 0), |range| (range.start().raw(), range.end().raw()));
+        let mut interprets = HashSet::new();
+        let mut types = HashSet::new();
+        for section in file
+            .sections
+            .iter()
+            .filter(|s| s.kind == SectionKind::Symbols)
+        {
+            for statement in &section.statements {
+                let (names, modmap) = match &statement.kind {
+                    StatementKind::Key { name, .. } => (vec![name], false),
+                    StatementKind::ModifierMap { keys, .. } => (
+                        keys.iter()
+                            .filter_map(|key| match &key.kind {
+                                ExprKind::KeyName(name) => Some(name),
+                                _ => None,
+                            })
+                            .collect(),
+                        true,
+                    ),
+                    _ => continue,
+                };
+                for name in names {
+                    let Some(key) = self
+                        .key_by_name(name.as_str())
+                        .filter(|&key| modmap || in_range(key))
+                    else {
+                        continue;
+                    };
+                    min = min.min(key.raw());
+                    max = max.max(key.raw());
+                    keys.insert(name.clone());
+                    interprets.extend(info.key_interprets(key).iter().copied());
+                    for layout in 0..self.num_layouts_for_key(key) {
+                        if let Some(key_type) = self.key_get_type(key, layout) {
+                            types.insert(key_type.name.clone());
+                        }
+                    }
+                }
+            }
+        }
+
+        file.sections
+            .retain(|section| section.kind != SectionKind::Geometry);
+        let mut interpret_idx = 0;
+        for section in &mut file.sections {
+            section
+                .statements
+                .retain_mut(|statement| match &mut statement.kind {
+                    StatementKind::Keycode { name, .. } => keys.contains(name),
+                    StatementKind::Alias { real, .. } => keys.contains(real),
+                    StatementKind::Var(var) if section.kind == SectionKind::Keycodes => {
+                        if keycodes.is_none() {
+                            return true;
+                        }
+                        let Some(value) = var.value.as_integer() else {
+                            return true;
+                        };
+                        let value = match var.field() {
+                            Some(field) if field.eq_ignore_ascii_case("minimum") => {
+                                value.max(i64::from(min))
+                            }
+                            Some(field) if field.eq_ignore_ascii_case("maximum") => {
+                                value.min(i64::from(max))
+                            }
+                            _ => return true,
+                        };
+                        var.value.kind = ExprKind::Integer { value, hex: false };
+                        true
+                    }
+                    StatementKind::KeyType { name, .. } => types.contains(name),
+                    StatementKind::Interpret { .. } => {
+                        interpret_idx += 1;
+                        interprets.contains(&(interpret_idx - 1))
+                    }
+                    StatementKind::IndicatorMap { body, .. } => !body.is_empty(),
+                    StatementKind::Key { name, .. } => keys.contains(name),
+                    StatementKind::ModifierMap { keys: names, .. } => {
+                        names.retain(|key| match &key.kind {
+                            ExprKind::KeyName(name) => keys.contains(name),
+                            _ => true,
+                        });
+                        !names.is_empty()
+                    }
+                    _ => true,
+                });
+        }
+        canonicalize(&mut file, CANONICAL_OMIT_DEFAULTS);
+
+        let keymap = Keymap::new_from_string(
+            context,
+            file.to_string(),
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .ok_or(MinimizeError::Compile)?;
+        if flags & MINIMIZE_VERIFY != 0 {
+            let reference = Keymap::new_from_string(
+                context,
+                self.get_as_string(KEYMAP_FORMAT_TEXT_V1),
+                KEYMAP_FORMAT_TEXT_V1,
+                KEYMAP_COMPILE_NO_FLAGS,
+            )
+            .ok_or(MinimizeError::Compile)?;
+            verify(&reference, &keymap, keycodes.as_ref())?;
+        }
+        Ok(keymap)
+    }
+}
+
+#[test]
+fn minimized_keymap() {
+    use super::CONTEXT_NO_FLAGS;
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        Some("ctrl:nocaps".into()),
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let minimized = keymap.minimized(&context, None, MINIMIZE_VERIFY).unwrap();
+    let size = |keymap: &Keymap| keymap.get_as_string(KEYMAP_FORMAT_TEXT_V1).len();
+    assert!(size(&minimized) < size(&keymap));
+    assert!(minimized.key_types().len() < keymap.key_types().len());
+
+    let range = Keycode::new(9)..=Keycode::new(66);
+    let main = keymap
+        .minimized(&context, Some(range), MINIMIZE_VERIFY)
+        .unwrap();
+    assert!(main.key_by_name("FK01").is_none());
+    assert!(main.key_by_name("CAPS").is_some());
+    // Aliases of the kept keys are kept.
+    assert!(main.key_by_name("AC12").is_some());
+    assert_eq!(main.key_by_name("AC12"), main.key_by_name("BKSL"));
+    assert!(main.key_by_name("MENU").is_none());
+}
@@ -5,31 +5,67 @@
     clippy::cast_sign_loss,
     clippy::too_many_arguments
 )]
+pub mod a11y;
+pub mod actions;
+pub mod canonical;
+pub mod components;
 pub mod compose;
+pub mod diff;
+pub mod editor;
 pub mod ffi;
+pub mod introspect;
 pub mod keysyms;
+pub mod label;
+pub mod leds;
+pub mod merge;
+pub mod minimize;
+pub mod remap;
+pub mod seat;
+pub mod snapshot;
+pub mod text;
+pub mod tracked;
 
 #[cfg(feature = "x11")]
 pub mod x11;
 
+pub use self::actions::*;
+pub use self::canonical::*;
+pub use self::components::*;
 pub use self::compose::*;
+pub use self::diff::*;
+pub use self::editor::*;
+pub use self::introspect::*;
+pub use self::label::*;
+pub use self::leds::*;
+pub use self::merge::*;
+pub use self::minimize::*;
+pub use self::seat::*;
+pub use self::snapshot::*;
+pub use self::tracked::*;
 use crate::xkb::ffi::*;
+use crate::xkb::introspect::KeymapInfo;
 
 #[cfg(feature = "wayland")]
 use memmap2::MmapOptions;
 #[cfg(feature = "wayland")]
-use std::os::unix::io::OwnedFd;
+use std::io::{self, Write};
+#[cfg(feature = "wayland")]
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
 
 use libc::{self, c_char, c_int, c_uint};
 use std::borrow::Borrow;
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
 use std::ffi::{CStr, CString};
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::io::Read;
 use std::iter::Iterator;
 use std::mem;
 use std::os::raw;
 use std::path::Path;
 use std::ptr::{null, null_mut};
+use std::rc::{Rc, Weak};
 use std::slice;
 use std::str;
 
@@ -210,6 +246,7 @@ pub const KEYMAP_FORMAT_TEXT_V1: u32 = 1;
 pub const KEYMAP_FORMAT_USE_ORIGINAL: u32 = 0xffff_ffff;
 
 /// Specifies the direction of the key (press / release).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 #[repr(C)]
 pub enum KeyDirection {
     /// the key was released
@@ -601,12 +638,103 @@ fn check_include_paths() {
 /// if you need to change it, you must create a new one.
 pub struct Keymap {
     ptr: *mut xkb_keymap,
+    cache: Rc<KeymapCache>,
+}
+
+/// Values derived from a keymap, computed on first use and shared by all
+/// the handles on the keymap in a thread, such as those returned by
+/// `State::get_keymap()`.
+#[derive(Default)]
+struct KeymapCache {
+    info: OnceCell<KeymapInfo>,
+    text: OnceCell<String>,
+    fingerprint: OnceCell<u64>,
+}
+
+thread_local! {
+    /// The caches of the keymaps which have handles in this thread.
+    static KEYMAP_CACHES: RefCell<HashMap<*mut xkb_keymap, Weak<KeymapCache>>> =
+        RefCell::new(HashMap::new());
+}
+
+/// Create a sealed memfd holding `data` and open it read-only, or return
+/// `None` if the system does not support sealed memfds.
+///
+/// The memfd is reopened through `/proc/self/fd`, so the returned descriptor
+/// has its own offset at the start of the file and cannot be used to write.
+#[cfg(all(feature = "wayland", any(target_os = "linux", target_os = "android")))]
+fn sealed_memfd(data: &[u8]) -> io::Result<Option<OwnedFd>> {
+    let name = CString::new("xkbcommon-keymap").unwrap();
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd < 0 {
+        let err = io::Error::last_os_error();
+        return match err.raw_os_error() {
+            Some(libc::ENOSYS | libc::EINVAL) => Ok(None),
+            _ => Err(err),
+        };
+    }
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+    let mut file = fs::File::from(fd);
+    file.write_all(data)?;
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    match fs::File::open(format!("/proc/self/fd/{}", file.as_raw_fd())) {
+        Ok(file) => Ok(Some(file.into())),
+        // `/proc` is not mounted.
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(all(
+    feature = "wayland",
+    not(any(target_os = "linux", target_os = "android"))
+))]
+fn sealed_memfd(_data: &[u8]) -> io::Result<Option<OwnedFd>> {
+    Ok(None)
+}
+
+/// Create an unlinked temporary file holding `data`, and open it read-only.
+///
+/// The file is created in `XDG_RUNTIME_DIR`, or the temporary directory if
+/// it is not set.
+#[cfg(feature = "wayland")]
+fn read_only_tempfile(data: &[u8]) -> io::Result<OwnedFd> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = std::env::var_os("XDG_RUNTIME_DIR").map_or_else(std::env::temp_dir, Into::into);
+    let template = dir.join("xkbcommon-keymap-XXXXXX");
+    let template = CString::new(template.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut template = template.into_bytes_with_nul();
+    let fd = unsafe { libc::mkstemp(template.as_mut_ptr().cast()) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { fs::File::from_raw_fd(fd) };
+    let path = Path::new(OsStr::from_bytes(&template[..template.len() - 1]));
+    let result = file.write_all(data).and_then(|()| fs::File::open(path));
+    fs::remove_file(path)?;
+    Ok(result?.into())
 }
 
 impl Keymap {
     #[allow(clippy::missing_safety_doc)]
     pub unsafe fn from_raw_ptr(ptr: *mut xkb_keymap) -> Keymap {
-        Keymap { ptr }
+        let cache = KEYMAP_CACHES.with(|caches| {
+            let mut caches = caches.borrow_mut();
+            if let Some(cache) = caches.get(&ptr).and_then(Weak::upgrade) {
+                return cache;
+            }
+            let cache = Rc::new(KeymapCache::default());
+            caches.insert(ptr, Rc::downgrade(&cache));
+            cache
+        });
+        Keymap { ptr, cache }
     }
 
     #[must_use]
@@ -703,7 +831,7 @@ impl Keymap {
             if pkeymap.is_null() {
                 None
             } else {
-                Some(Keymap { ptr: pkeymap })
+                Some(Keymap::from_raw_ptr(pkeymap))
             }
         }
     }
@@ -750,7 +878,7 @@ impl Keymap {
             if ptr.is_null() {
                 None
             } else {
-                Some(Keymap { ptr })
+                Some(Keymap::from_raw_ptr(ptr))
             }
         }
     }
@@ -758,6 +886,7 @@ impl Keymap {
     #[cfg(feature = "wayland")]
     /// Create a keymap from a file descriptor.
     /// The file is mapped to memory and the keymap is created from the mapped memory buffer.
+    /// A trailing NUL byte, as sent by Wayland compositors, is ignored.
     ///
     /// # Safety
     /// The file descriptor must be valid and all safety concerns of mapping files to memory
@@ -774,14 +903,46 @@ impl Keymap {
             .len(size as usize)
             // Starting in version 7 of the wl_keyboard protocol, the keymap must be mapped using MAP_PRIVATE.
             .map_copy_read_only(&fs::File::from(fd))?;
-        let ptr = xkb_keymap_new_from_buffer(context.ptr, map.as_ptr().cast(), size, format, flags);
+        let buffer = map.strip_suffix(&[0]).unwrap_or(&map);
+        let ptr = xkb_keymap_new_from_buffer(
+            context.ptr,
+            buffer.as_ptr().cast(),
+            buffer.len(),
+            format,
+            flags,
+        );
         if ptr.is_null() {
             Ok(None)
         } else {
-            Ok(Some(Keymap { ptr }))
+            Ok(Some(Keymap::from_raw_ptr(ptr)))
         }
     }
 
+    #[cfg(feature = "wayland")]
+    /// Write the keymap to a read-only file descriptor, as sent by Wayland
+    /// compositors in `wl_keyboard.keymap` events.
+    ///
+    /// The keymap is written in `format`, with a trailing NUL byte, to a memfd
+    /// sealed against writing, shrinking, growing and further sealing. On
+    /// systems without sealed memfds, it is written to an unlinked temporary
+    /// file. Either way, the returned descriptor is opened read-only and its
+    /// offset is at the start of the file.
+    ///
+    /// Returns the file descriptor and the size of the keymap, including the
+    /// NUL byte.
+    ///
+    /// # Errors
+    /// Returns an error if the file cannot be created or written.
+    pub fn to_sealed_fd(&self, format: KeymapFormat) -> io::Result<(OwnedFd, usize)> {
+        let mut data = self.get_as_string(format).into_bytes();
+        data.push(0);
+        let fd = match sealed_memfd(&data)? {
+            Some(fd) => fd,
+            None => read_only_tempfile(&data)?,
+        };
+        Ok((fd, data.len()))
+    }
+
     /// Get the compiled keymap as a string.
     ///
     ///  keymap The keymap to get as a string.
@@ -807,6 +968,42 @@ impl Keymap {
         }
     }
 
+    /// Get a fingerprint of the content of the keymap.
+    ///
+    /// This is the 64-bit FNV-1a hash of `get_as_string(KEYMAP_FORMAT_TEXT_V1)`,
+    /// computed on first use. It is stable across processes and versions of
+    /// this crate, but may change with the version of libxkbcommon, which
+    /// writes the text.
+    ///
+    /// Keymaps hash the same when their fingerprints are equal, and compare
+    /// equal when their texts are equal; use `ptr_eq()` to tell whether they
+    /// are the same object.
+    #[must_use]
+    pub fn fingerprint(&self) -> u64 {
+        *self.cache.fingerprint.get_or_init(|| {
+            self.text()
+                .bytes()
+                .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                    (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+                })
+        })
+    }
+
+    /// Get the text of the keymap in `KEYMAP_FORMAT_TEXT_V1`, computed on
+    /// first use.
+    fn text(&self) -> &str {
+        self.cache
+            .text
+            .get_or_init(|| self.get_as_string(KEYMAP_FORMAT_TEXT_V1))
+    }
+
+    /// Check whether two keymaps are the same object, as opposed to having
+    /// the same content.
+    #[must_use]
+    pub fn ptr_eq(&self, other: &Keymap) -> bool {
+        self.ptr == other.ptr
+    }
+
     /// Get the minimum keycode in the keymap.
     #[must_use]
     pub fn min_keycode(&self) -> Keycode {
@@ -883,6 +1080,29 @@ impl Keymap {
         }
     }
 
+    /// Get a human-readable label for a modifier by index.
+    ///
+    /// See `mod_display_label()`. Unlike it, the real modifiers `Mod1` to
+    /// `Mod5` are labeled after the virtual modifier this keymap maps to them,
+    /// e.g. `Mod5` is labeled `AltGr` only if `LevelThree` is mapped to it.
+    /// They are labeled with their name if no common virtual modifier is.
+    ///
+    /// If the index is invalid, returns "".
+    #[must_use]
+    pub fn mod_get_label(&self, idx: ModIndex, style: LabelStyle) -> String {
+        let name = self.mod_get_name(idx);
+        if (3..8).contains(&idx) {
+            return label::LABELED_VMODS
+                .iter()
+                .find(|&&vmod| self.mod_get_mask(self.mod_get_index(vmod)) == 1 << idx)
+                .map_or_else(|| name.to_owned(), |vmod| mod_display_label(vmod, style));
+        }
+        match name {
+            "" => String::new(),
+            name => mod_display_label(name, style),
+        }
+    }
+
     /// Get the index of a modifier by name.
     ///
     /// Returns The index. If no modifier with this name exists, returns
@@ -895,6 +1115,65 @@ impl Keymap {
         }
     }
 
+    /// Get the mask of real modifiers a modifier is mapped to.
+    ///
+    /// The modifier masks serialized by `State::serialize_mods()` (and sent
+    /// over the wire by Wayland compositors) are made of real modifiers.
+    /// Real modifiers map to themselves; virtual modifiers (e.g.
+    /// `xkb::VMOD_NAME_ALT`) map to the real modifiers assigned to them by
+    /// the keymap, which may be none.
+    ///
+    /// This uses `xkb_keymap_mod_get_mask()` with the `v1_10` feature, and
+    /// resolves the mapping through a scratch `State` otherwise.
+    ///
+    /// Returns 0 if the index is invalid.
+    #[must_use]
+    pub fn mod_get_mask(&self, idx: ModIndex) -> ModMask {
+        #[cfg(feature = "v1_10")]
+        unsafe {
+            xkb_keymap_mod_get_mask(self.ptr, idx)
+        }
+        #[cfg(not(feature = "v1_10"))]
+        {
+            if idx < 8 {
+                return 1 << idx;
+            }
+            if idx >= self.num_mods().min(ModMask::BITS) {
+                return 0;
+            }
+            let mut state = State::new(self);
+            state.update_mask(1 << idx, 0, 0, 0, 0, 0);
+            // Older libxkbcommon keeps the virtual modifier bit in the mask.
+            state.serialize_mods(STATE_MODS_DEPRESSED) & 0xff
+        }
+    }
+
+    /// Get the names of the modifiers set in a mask.
+    ///
+    /// This decodes each bit of the mask to the modifier of the same index.
+    /// Masks serialized by `State::serialize_mods()` only contain real
+    /// modifiers; use `names_to_mask()` to compare them with virtual
+    /// modifiers.
+    pub fn mask_to_names(&self, mask: ModMask) -> impl Iterator<Item = &str> {
+        (0..self.num_mods().min(ModMask::BITS))
+            .filter(move |idx| mask & (1 << idx) != 0)
+            .map(move |idx| self.mod_get_name(idx))
+    }
+
+    /// Get the mask of real modifiers the named modifiers are mapped to,
+    /// as with `mod_get_mask()`.
+    ///
+    /// Returns `None` if a modifier does not exist in the keymap.
+    #[must_use]
+    pub fn names_to_mask(&self, names: &[&str]) -> Option<ModMask> {
+        names
+            .iter()
+            .try_fold(0, |mask, name| match self.mod_get_index(*name) {
+                MOD_INVALID => None,
+                idx => Some(mask | self.mod_get_mask(idx)),
+            })
+    }
+
     /// Returns an iterator to the layouts in this keymap
     #[must_use]
     pub fn layouts(&self) -> KeymapLayouts {
@@ -1169,19 +1448,117 @@ impl Clone for Keymap {
         unsafe {
             Keymap {
                 ptr: xkb_keymap_ref(self.ptr),
+                cache: Rc::clone(&self.cache),
             }
         }
     }
 }
 
+impl PartialEq for Keymap {
+    fn eq(&self, other: &Keymap) -> bool {
+        // Fingerprints can collide, so equal fingerprints are confirmed by
+        // comparing the texts.
+        self.ptr_eq(other)
+            || (self.fingerprint() == other.fingerprint() && self.text() == other.text())
+    }
+}
+
+impl Eq for Keymap {}
+
+impl Hash for Keymap {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fingerprint().hash(state);
+    }
+}
+
 impl Drop for Keymap {
     fn drop(&mut self) {
+        if Rc::strong_count(&self.cache) == 1 {
+            // The thread may be exiting, in which case the table is gone.
+            let _ = KEYMAP_CACHES.try_with(|caches| caches.borrow_mut().remove(&self.ptr));
+        }
         unsafe {
             xkb_keymap_unref(self.ptr);
         }
     }
 }
 
+#[test]
+fn keymap_equality() {
+    use std::collections::hash_map::DefaultHasher;
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let names = |layout: &str| {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layout,
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let (us, us_again, de) = (names("us"), names("us"), names("de"));
+    assert!(us == us_again && !us.ptr_eq(&us_again));
+    assert!(us.clone().ptr_eq(&us));
+    assert_ne!(us.fingerprint(), de.fingerprint());
+    let hash = |keymap: &Keymap| {
+        let mut hasher = DefaultHasher::new();
+        keymap.hash(&mut hasher);
+        hasher.finish()
+    };
+    assert_eq!(hash(&us), hash(&us_again));
+}
+
+#[cfg(feature = "wayland")]
+#[test]
+fn keymap_sealed_fd() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let text = keymap.get_as_string(KEYMAP_FORMAT_TEXT_V1);
+    let (fd, size) = keymap.to_sealed_fd(KEYMAP_FORMAT_TEXT_V1).unwrap();
+    assert_eq!(size, text.len() + 1);
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+        let expected = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+        assert_eq!(seals & expected, expected);
+    }
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    assert_eq!(flags & libc::O_ACCMODE, libc::O_RDONLY);
+    assert_eq!(unsafe { libc::lseek(fd.as_raw_fd(), 0, libc::SEEK_CUR) }, 0);
+    let compiled = |fd| unsafe {
+        Keymap::new_from_fd(
+            &context,
+            fd,
+            size,
+            KEYMAP_FORMAT_TEXT_V1,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+        .unwrap()
+    };
+    assert!(compiled(fd) == keymap);
+
+    let mut data = text.into_bytes();
+    data.push(0);
+    let fd = read_only_tempfile(&data).unwrap();
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFL) };
+    assert_eq!(flags & libc::O_ACCMODE, libc::O_RDONLY);
+    assert!(compiled(fd) == keymap);
+}
+
 /// iterator to the modifiers in a Keymap
 pub struct KeymapMods<'a> {
     keymap: &'a Keymap,
@@ -1366,6 +1743,224 @@ impl State {
         }
     }
 
+    /// Update the latched and locked modifiers and layouts of a keyboard
+    /// state, leaving the depressed components untouched.
+    ///
+    /// This entry point is intended for server applications which need to
+    /// change the latched or locked state without faking key events, e.g.
+    /// to implement on-screen keyboards or accessibility latches. Unlike
+    /// `update_mask()`, it can be used together with `update_key()`.
+    ///
+    /// Only the modifiers in `affect_latched_mods` are set to their value
+    /// in `latched_mods`, and similarly for the locked modifiers. The
+    /// latched and locked layouts are only updated if the corresponding
+    /// `affect_*_layout` argument is true. Layouts are relative values, as
+    /// for `update_mask()`.
+    ///
+    /// Requires libxkbcommon 1.8 or newer.
+    ///
+    /// Returns a mask of state components that have changed as a result of
+    /// the update. If nothing in the state has changed, returns 0.
+    #[cfg(feature = "v1_8")]
+    pub fn update_latched_locked(
+        &mut self,
+        affect_latched_mods: ModMask,
+        latched_mods: ModMask,
+        affect_latched_layout: bool,
+        latched_layout: i32,
+        affect_locked_mods: ModMask,
+        locked_mods: ModMask,
+        affect_locked_layout: bool,
+        locked_layout: i32,
+    ) -> StateComponent {
+        unsafe {
+            xkb_state_update_latched_locked(
+                self.ptr,
+                affect_latched_mods,
+                latched_mods,
+                affect_latched_layout,
+                latched_layout,
+                affect_locked_mods,
+                locked_mods,
+                affect_locked_layout,
+                locked_layout,
+            )
+        }
+    }
+
+    /// Like `update_key()`, but reports the old and new values of the state
+    /// components instead of only a mask of the changed ones.
+    ///
+    /// The returned `StateChanges` avoids having to call `serialize_mods()`
+    /// and `serialize_layout()` for each component and compare them against
+    /// cached values. It is cheap enough to be called on every key event.
+    pub fn update_key_with_changes(
+        &mut self,
+        key: Keycode,
+        direction: KeyDirection,
+    ) -> StateChanges {
+        let old = self.values();
+        let components = self.update_key(key, direction);
+        self.changes_since(old, components)
+    }
+
+    /// Like `update_mask()`, but reports the old and new values of the state
+    /// components instead of only a mask of the changed ones.
+    pub fn update_mask_with_changes(
+        &mut self,
+        depressed_mods: ModMask,
+        latched_mods: ModMask,
+        locked_mods: ModMask,
+        depressed_layout: LayoutIndex,
+        latched_layout: LayoutIndex,
+        locked_layout: LayoutIndex,
+    ) -> StateChanges {
+        let old = self.values();
+        let components = self.update_mask(
+            depressed_mods,
+            latched_mods,
+            locked_mods,
+            depressed_layout,
+            latched_layout,
+            locked_layout,
+        );
+        self.changes_since(old, components)
+    }
+
+    /// Lock the given layout, preserving every other modifier and layout
+    /// state component.
+    ///
+    /// Indices out of range are wrapped around the number of layouts of
+    /// the keymap.
+    pub fn lock_layout(&mut self, idx: LayoutIndex) -> StateChanges {
+        let num_layouts = unsafe { xkb_keymap_num_layouts(xkb_state_get_keymap(self.ptr)) };
+        let snapshot = self.snapshot();
+        self.update_mask_with_changes(
+            snapshot.depressed_mods,
+            snapshot.latched_mods,
+            snapshot.locked_mods,
+            snapshot.depressed_layout,
+            snapshot.latched_layout,
+            idx.checked_rem(num_layouts).unwrap_or(0),
+        )
+    }
+
+    /// Lock the layout with the given name, preserving every other modifier
+    /// and layout state component.
+    ///
+    /// Returns `None` if no layout has this name. If several layouts have
+    /// this name, the one with the lowest index is locked.
+    pub fn lock_layout_by_name<S: Borrow<str> + ?Sized>(
+        &mut self,
+        name: &S,
+    ) -> Option<StateChanges> {
+        match self.get_keymap().layout_get_index(name) {
+            LAYOUT_INVALID => None,
+            idx => Some(self.lock_layout(idx)),
+        }
+    }
+
+    /// Lock the layout following the locked layout, wrapping around to the
+    /// first one.
+    pub fn next_layout(&mut self) -> StateChanges {
+        self.shift_locked_layout(1)
+    }
+
+    /// Lock the layout preceding the locked layout, wrapping around to the
+    /// last one.
+    pub fn previous_layout(&mut self) -> StateChanges {
+        let num_layouts = unsafe { xkb_keymap_num_layouts(xkb_state_get_keymap(self.ptr)) };
+        self.shift_locked_layout(num_layouts.saturating_sub(1))
+    }
+
+    fn shift_locked_layout(&mut self, offset: LayoutIndex) -> StateChanges {
+        let num_layouts = unsafe { xkb_keymap_num_layouts(xkb_state_get_keymap(self.ptr)) };
+        let locked = self
+            .serialize_layout(STATE_LAYOUT_LOCKED)
+            .checked_rem(num_layouts)
+            .unwrap_or(0);
+        self.lock_layout(locked + offset)
+    }
+
+    /// Set or clear locked modifiers by name, preserving every other modifier
+    /// and layout state component.
+    ///
+    /// Virtual modifiers (e.g. `xkb::VMOD_NAME_NUM`) are resolved to the real
+    /// modifiers they are mapped to in the keymap, so that LEDs like
+    /// `xkb::LED_NAME_NUM` are kept consistent.
+    ///
+    /// Returns `None`, without updating the state, if a modifier does not
+    /// exist in the keymap.
+    pub fn set_locked_mods_by_name(
+        &mut self,
+        names: &[&str],
+        locked: bool,
+    ) -> Option<StateChanges> {
+        let mask = self.get_keymap().names_to_mask(names)?;
+        let mut snapshot = self.snapshot();
+        if locked {
+            snapshot.locked_mods |= mask;
+        } else {
+            snapshot.locked_mods &= !mask;
+        }
+        Some(self.restore(&snapshot))
+    }
+
+    /// Set or clear latched modifiers by name, preserving every other modifier
+    /// and layout state component.
+    ///
+    /// See `set_locked_mods_by_name()`.
+    pub fn set_latched_mods_by_name(
+        &mut self,
+        names: &[&str],
+        latched: bool,
+    ) -> Option<StateChanges> {
+        let mask = self.get_keymap().names_to_mask(names)?;
+        let mut snapshot = self.snapshot();
+        if latched {
+            snapshot.latched_mods |= mask;
+        } else {
+            snapshot.latched_mods &= !mask;
+        }
+        Some(self.restore(&snapshot))
+    }
+
+    /// Get the current value of every modifier, layout and LED state
+    /// component.
+    #[must_use]
+    pub fn values(&self) -> StateValues {
+        StateValues {
+            depressed_mods: self.serialize_mods(STATE_MODS_DEPRESSED),
+            latched_mods: self.serialize_mods(STATE_MODS_LATCHED),
+            locked_mods: self.serialize_mods(STATE_MODS_LOCKED),
+            effective_mods: self.serialize_mods(STATE_MODS_EFFECTIVE),
+            depressed_layout: self.serialize_layout(STATE_LAYOUT_DEPRESSED),
+            latched_layout: self.serialize_layout(STATE_LAYOUT_LATCHED),
+            locked_layout: self.serialize_layout(STATE_LAYOUT_LOCKED),
+            effective_layout: self.serialize_layout(STATE_LAYOUT_EFFECTIVE),
+            leds: self.serialize_leds(),
+        }
+    }
+
+    /// Get the mask of the active LEDs.
+    #[must_use]
+    pub fn serialize_leds(&self) -> LedMask {
+        unsafe {
+            let num_leds = xkb_keymap_num_leds(xkb_state_get_keymap(self.ptr)).min(LedMask::BITS);
+            (0..num_leds)
+                .filter(|&idx| xkb_state_led_index_is_active(self.ptr, idx) == 1)
+                .fold(0, |mask, idx| mask | (1 << idx))
+        }
+    }
+
+    fn changes_since(&self, old: StateValues, components: StateComponent) -> StateChanges {
+        StateChanges {
+            components,
+            old,
+            new: if components == 0 { old } else { self.values() },
+        }
+    }
+
     /// Get the keysyms obtained from pressing a particular key in a given
     /// keyboard state.
     ///
@@ -1613,6 +2208,89 @@ impl State {
     }
 }
 
+/// Values of all the state components of a `State`, as returned by
+/// `State::values()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StateValues {
+    pub depressed_mods: ModMask,
+    pub latched_mods: ModMask,
+    pub locked_mods: ModMask,
+    pub effective_mods: ModMask,
+    pub depressed_layout: LayoutIndex,
+    pub latched_layout: LayoutIndex,
+    pub locked_layout: LayoutIndex,
+    pub effective_layout: LayoutIndex,
+    pub leds: LedMask,
+}
+
+impl StateValues {
+    /// Get the modifier mask of the given component.
+    ///
+    /// `component` must be one of the `xkb::STATE_MODS_*` values.
+    #[must_use]
+    pub fn mods(&self, component: StateComponent) -> ModMask {
+        match component {
+            STATE_MODS_DEPRESSED => self.depressed_mods,
+            STATE_MODS_LATCHED => self.latched_mods,
+            STATE_MODS_LOCKED => self.locked_mods,
+            STATE_MODS_EFFECTIVE => self.effective_mods,
+            _ => 0,
+        }
+    }
+
+    /// Get the layout index of the given component.
+    ///
+    /// `component` must be one of the `xkb::STATE_LAYOUT_*` values.
+    #[must_use]
+    pub fn layout(&self, component: StateComponent) -> LayoutIndex {
+        match component {
+            STATE_LAYOUT_DEPRESSED => self.depressed_layout,
+            STATE_LAYOUT_LATCHED => self.latched_layout,
+            STATE_LAYOUT_LOCKED => self.locked_layout,
+            STATE_LAYOUT_EFFECTIVE => self.effective_layout,
+            _ => 0,
+        }
+    }
+}
+
+/// Report of a state update, as returned by `State::update_key_with_changes()`
+/// and `State::update_mask_with_changes()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct StateChanges {
+    /// A mask of the state components that have changed.
+    pub components: StateComponent,
+    /// Values of the state components before the update.
+    pub old: StateValues,
+    /// Values of the state components after the update.
+    pub new: StateValues,
+}
+
+impl StateChanges {
+    /// Whether nothing in the state has changed.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.components == 0
+    }
+
+    /// Whether any of the given state components has changed.
+    #[must_use]
+    pub fn contains(&self, components: StateComponent) -> bool {
+        self.components & components != 0
+    }
+
+    /// The LEDs that were turned on by the update.
+    #[must_use]
+    pub fn leds_on(&self) -> LedMask {
+        self.new.leds & !self.old.leds
+    }
+
+    /// The LEDs that were turned off by the update.
+    #[must_use]
+    pub fn leds_off(&self) -> LedMask {
+        self.old.leds & !self.new.leds
+    }
+}
+
 impl Clone for State {
     fn clone(&self) -> State {
         unsafe {
@@ -1630,3 +2308,224 @@ impl Drop for State {
         }
     }
 }
+
+#[test]
+fn state_changes() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let caps_led = 1 << keymap.led_get_index(LED_NAME_CAPS);
+    let caps = keymap.key_by_name("CAPS").unwrap();
+    let lock = 1 << keymap.mod_get_index(MOD_NAME_CAPS);
+    let mut state = State::new(&keymap);
+    assert_eq!(state.serialize_leds(), 0);
+
+    let changes = state.update_key_with_changes(caps, KeyDirection::Down);
+    assert!(changes.contains(STATE_MODS_DEPRESSED | STATE_MODS_LOCKED | STATE_LEDS));
+    assert_eq!(
+        (changes.old.locked_mods, changes.new.locked_mods),
+        (0, lock)
+    );
+    assert_eq!(changes.new.depressed_mods, lock);
+    assert_eq!((changes.leds_on(), changes.leds_off()), (caps_led, 0));
+    assert_eq!(state.serialize_leds(), caps_led);
+
+    let changes = state.update_key_with_changes(caps, KeyDirection::Up);
+    assert!(changes.contains(STATE_MODS_DEPRESSED) && !changes.contains(STATE_LEDS));
+    assert_eq!((changes.leds_on(), changes.leds_off()), (0, 0));
+
+    let changes = state.update_mask_with_changes(0, 0, 0, 0, 0, 0);
+    assert_eq!(
+        changes.components,
+        STATE_MODS_LOCKED | STATE_MODS_EFFECTIVE | STATE_LEDS
+    );
+    assert_eq!((changes.leds_on(), changes.leds_off()), (0, caps_led));
+    assert_eq!(state.serialize_leds(), 0);
+    assert!(state.update_mask_with_changes(0, 0, 0, 0, 0, 0).is_empty());
+}
+
+#[test]
+fn state_lock_layout() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de,fr",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let shift = 1 << keymap.mod_get_index(MOD_NAME_SHIFT);
+    let mut state = State::new(&keymap);
+    state.update_mask(shift, 0, 0, 0, 0, 0);
+    let locked = |state: &State| state.serialize_layout(STATE_LAYOUT_LOCKED);
+
+    let changes = state.lock_layout(2);
+    assert_eq!(
+        (changes.old.locked_layout, changes.new.locked_layout),
+        (0, 2)
+    );
+    assert_eq!(state.serialize_mods(STATE_MODS_DEPRESSED), shift);
+    assert!(state.lock_layout(2).is_empty());
+    state.lock_layout(4);
+    assert_eq!(locked(&state), 1);
+
+    state.next_layout();
+    assert_eq!(locked(&state), 2);
+    state.next_layout();
+    assert_eq!(locked(&state), 0);
+    state.previous_layout();
+    assert_eq!(locked(&state), 2);
+    state.previous_layout();
+    assert_eq!(locked(&state), 1);
+
+    let changes = state.lock_layout_by_name("English (US)").unwrap();
+    assert_eq!(changes.new.effective_layout, 0);
+    assert!(state.lock_layout_by_name("Klingon").is_none());
+    assert_eq!(locked(&state), 0);
+    assert_eq!(state.serialize_mods(STATE_MODS_DEPRESSED), shift);
+}
+
+#[test]
+fn state_set_mods_by_name() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let num_led = 1 << keymap.led_get_index(LED_NAME_NUM);
+    let shift = 1 << keymap.mod_get_index(MOD_NAME_SHIFT);
+    let mut state = State::new(&keymap);
+    state.update_mask(shift, 0, 0, 0, 0, 0);
+
+    // The virtual modifier is resolved to Mod2, which drives the LED.
+    let changes = state
+        .set_locked_mods_by_name(&[VMOD_NAME_NUM], true)
+        .unwrap();
+    assert_eq!(
+        changes.new.locked_mods,
+        1 << keymap.mod_get_index(MOD_NAME_NUM)
+    );
+    assert_eq!(changes.leds_on(), num_led);
+    let changes = state
+        .set_locked_mods_by_name(&[MOD_NAME_NUM], false)
+        .unwrap();
+    assert_eq!((changes.new.locked_mods, changes.leds_off()), (0, num_led));
+
+    let changes = state
+        .set_latched_mods_by_name(&[MOD_NAME_CTRL], true)
+        .unwrap();
+    assert_eq!(
+        changes.new.latched_mods,
+        1 << keymap.mod_get_index(MOD_NAME_CTRL)
+    );
+    assert!(state
+        .set_latched_mods_by_name(&[MOD_NAME_CTRL, "Foo"], false)
+        .is_none());
+    assert_ne!(state.serialize_mods(STATE_MODS_LATCHED), 0);
+    state
+        .set_latched_mods_by_name(&[MOD_NAME_CTRL], false)
+        .unwrap();
+    assert_eq!(state.serialize_mods(STATE_MODS_LATCHED), 0);
+    assert_eq!(state.serialize_mods(STATE_MODS_DEPRESSED), shift);
+}
+
+#[test]
+fn keymap_mod_masks() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let real = |name| 1 << keymap.mod_get_index(name);
+    let mask = |name| keymap.mod_get_mask(keymap.mod_get_index(name));
+    // Real modifiers map to themselves, virtual ones to their real modifiers.
+    assert_eq!(mask(MOD_NAME_SHIFT), real(MOD_NAME_SHIFT));
+    assert_eq!(mask(MOD_NAME_MOD5), real(MOD_NAME_MOD5));
+    assert_eq!(mask(VMOD_NAME_ALT), real(MOD_NAME_ALT));
+    assert_eq!(mask(VMOD_NAME_NUM), real(MOD_NAME_NUM));
+    assert_eq!(mask(VMOD_NAME_LEVEL3), real(MOD_NAME_MOD5));
+    assert_eq!(keymap.mod_get_mask(MOD_INVALID), 0);
+    assert_eq!(keymap.mod_get_mask(keymap.num_mods()), 0);
+
+    let names: Vec<_> = keymap
+        .mask_to_names(real(MOD_NAME_SHIFT) | real(MOD_NAME_LOGO))
+        .collect();
+    assert_eq!(names, [MOD_NAME_SHIFT, MOD_NAME_LOGO]);
+    assert_eq!(keymap.mask_to_names(0).count(), 0);
+
+    assert_eq!(
+        keymap.names_to_mask(&[MOD_NAME_CTRL, VMOD_NAME_SUPER]),
+        Some(real(MOD_NAME_CTRL) | real(MOD_NAME_LOGO))
+    );
+    assert_eq!(keymap.names_to_mask(&[]), Some(0));
+    assert_eq!(keymap.names_to_mask(&[MOD_NAME_CTRL, "Foo"]), None);
+}
+
+#[cfg(feature = "v1_8")]
+#[test]
+fn state_update_latched_locked() {
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let shift = 1 << keymap.mod_get_index(MOD_NAME_SHIFT);
+    let ctrl = 1 << keymap.mod_get_index(MOD_NAME_CTRL);
+    let mut state = State::new(&keymap);
+    state.update_key(keymap.key_by_name("LFSH").unwrap(), KeyDirection::Down);
+
+    let changed = state.update_latched_locked(ctrl, ctrl, false, 0, 0, 0, true, 1);
+    assert_eq!(
+        changed & (STATE_MODS_LATCHED | STATE_LAYOUT_LOCKED),
+        STATE_MODS_LATCHED | STATE_LAYOUT_LOCKED
+    );
+    assert_eq!(state.serialize_mods(STATE_MODS_LATCHED), ctrl);
+    assert_eq!(state.serialize_layout(STATE_LAYOUT_LOCKED), 1);
+    // Depressed modifiers are left untouched.
+    assert_eq!(state.serialize_mods(STATE_MODS_DEPRESSED), shift);
+
+    // Only the affected modifiers change.
+    let lock = 1 << keymap.mod_get_index(MOD_NAME_CAPS);
+    let changed = state.update_latched_locked(ctrl, 0, false, 0, lock, lock, false, 0);
+    assert_eq!(
+        changed & (STATE_MODS_LATCHED | STATE_MODS_LOCKED),
+        STATE_MODS_LATCHED | STATE_MODS_LOCKED
+    );
+    assert_eq!(state.serialize_mods(STATE_MODS_LATCHED), 0);
+    assert_eq!(state.serialize_mods(STATE_MODS_LOCKED), lock);
+    assert_eq!(state.serialize_mods(STATE_MODS_EFFECTIVE), shift | lock);
+    assert_eq!(state.serialize_layout(STATE_LAYOUT_LOCKED), 1);
+    assert_eq!(
+        state.update_latched_locked(0, 0, false, 0, 0, 0, false, 0),
+        0
+    );
+}
@@ -0,0 +1,489 @@
+//! Keycode remapping layer.
+//!
+//! A `Remapper` sits in front of `State::update_key()` and rewrites the key
+//! events of the physical keyboard before they reach the keymap. It allows
+//! key swaps and dual-role keys (e.g. "Caps Lock is Escape on tap and
+//! Control on hold") which work on any keymap, without writing XKB symbols.
+//!
+//! The rules are written in a simple line-based format, with key names as
+//! found in the keymap (see `Keymap::key_by_name()`):
+//!
+//! ```text
+//! # Static rewrite of a key to another one.
+//! map RALT RCTL
+//! # Exchange two keys.
+//! swap LCTL CAPS
+//! # Dual-role key: tap key, then hold key. Options are the timeout after
+//! # which the key is held (200 ms by default), and permissive-hold, which
+//! # holds the key as soon as another key is pressed and released.
+//! tap-hold CAPS ESC LCTL timeout=180 permissive-hold
+//! # One-shot modifier: tapping it applies it to the next key only.
+//! one-shot LFSH
+//! ```
+//!
+//! The remapper is driven by the timestamps of the key events, which may
+//! come from any monotonic clock, and does not read the time by itself.
+
+use super::{KeyDirection, Keycode, Keymap};
+use std::error::Error;
+use std::fmt;
+use std::time::Duration;
+
+/// Default delay after which a tap-hold key is held.
+pub const DEFAULT_TAP_HOLD_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// A key event, as fed to `State::update_key()`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyEvent {
+    pub key: Keycode,
+    pub direction: KeyDirection,
+}
+
+impl KeyEvent {
+    #[must_use]
+    pub fn new(key: Keycode, direction: KeyDirection) -> KeyEvent {
+        KeyEvent { key, direction }
+    }
+}
+
+/// A remapping rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Rule {
+    /// Rewrite the events of key `from` to key `to`.
+    Map { from: Keycode, to: Keycode },
+    /// Dual-role key: `key` acts as `tap` when tapped, and as `hold` when
+    /// held for `timeout`. With `permissive_hold`, it also acts as `hold` as
+    /// soon as another key is pressed and released while it is held.
+    TapHold {
+        key: Keycode,
+        tap: Keycode,
+        hold: Keycode,
+        timeout: Duration,
+        permissive_hold: bool,
+    },
+    /// One-shot modifier: when `key` is tapped, it stays pressed until the
+    /// next key is released.
+    OneShot { key: Keycode },
+}
+
+/// Error in a remapping configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    /// Line of the error, starting at 1.
+    pub line: usize,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigErrorKind {
+    /// The directive is not known.
+    UnknownDirective(String),
+    /// The key name does not exist in the keymap.
+    UnknownKey(String),
+    /// The option is not known, or its value is invalid.
+    InvalidOption(String),
+    /// The directive does not have the expected number of keys.
+    WrongArity,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            ConfigErrorKind::UnknownDirective(d) => write!(f, "unknown directive \"{d}\""),
+            ConfigErrorKind::UnknownKey(k) => write!(f, "unknown key \"{k}\""),
+            ConfigErrorKind::InvalidOption(o) => write!(f, "invalid option \"{o}\""),
+            ConfigErrorKind::WrongArity => write!(f, "wrong number of keys"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+/// Parse a remapping configuration, resolving key names with a keymap.
+///
+/// # Errors
+/// Returns an error on the first invalid line.
+pub fn parse_rules(keymap: &Keymap, config: &str) -> Result<Vec<Rule>, ConfigError> {
+    let mut rules = Vec::new();
+    for (idx, line) in config.lines().enumerate() {
+        let err = |kind| ConfigError {
+            line: idx + 1,
+            kind,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut words = line.split_whitespace();
+        let Some(directive) = words.next() else {
+            continue;
+        };
+        let (options, names): (Vec<&str>, Vec<&str>) =
+            words.partition(|word| word.contains('=') || *word == "permissive-hold");
+        let keys = names
+            .iter()
+            .map(|name| {
+                let name = name.trim_start_matches('<').trim_end_matches('>');
+                keymap
+                    .key_by_name(name)
+                    .ok_or_else(|| err(ConfigErrorKind::UnknownKey(name.to_owned())))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut timeout = DEFAULT_TAP_HOLD_TIMEOUT;
+        let mut permissive_hold = false;
+        for option in &options {
+            match option.split_once('=') {
+                _ if directive != "tap-hold" => {
+                    return Err(err(ConfigErrorKind::InvalidOption((*option).to_owned())))
+                }
+                Some(("timeout", ms)) => match ms.parse() {
+                    Ok(ms) => timeout = Duration::from_millis(ms),
+                    Err(_) => {
+                        return Err(err(ConfigErrorKind::InvalidOption((*option).to_owned())))
+                    }
+                },
+                None => permissive_hold = true,
+                Some(_) => return Err(err(ConfigErrorKind::InvalidOption((*option).to_owned()))),
+            }
+        }
+        match (directive, keys.as_slice()) {
+            ("map", &[from, to]) => rules.push(Rule::Map { from, to }),
+            ("swap", &[a, b]) => {
+                rules.push(Rule::Map { from: a, to: b });
+                rules.push(Rule::Map { from: b, to: a });
+            }
+            ("tap-hold", &[key, tap, hold]) => rules.push(Rule::TapHold {
+                key,
+                tap,
+                hold,
+                timeout,
+                permissive_hold,
+            }),
+            ("one-shot", &[key]) => rules.push(Rule::OneShot { key }),
+            ("map" | "swap" | "tap-hold" | "one-shot", _) => {
+                return Err(err(ConfigErrorKind::WrongArity))
+            }
+            _ => return Err(err(ConfigErrorKind::UnknownDirective(directive.to_owned()))),
+        }
+    }
+    Ok(rules)
+}
+
+/// A tap-hold key which is pressed, but not yet resolved as tap or hold.
+struct Undecided {
+    key: Keycode,
+    tap: Keycode,
+    hold: Keycode,
+    deadline: Duration,
+    permissive_hold: bool,
+    /// Events which happened while undecided, to replay once resolved.
+    buffered: Vec<(KeyEvent, Duration)>,
+}
+
+/// State of a one-shot modifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OneShot {
+    /// Pressed, and no other key pressed in the meantime.
+    Pressed(Keycode),
+    /// Tapped, waiting for the next key.
+    Armed(Keycode),
+    /// Tapped, applying to the given key until it is released.
+    Applied(Keycode, Keycode),
+    /// Tapped again to cancel it, waiting for the release.
+    Cancelled(Keycode),
+}
+
+/// Keycode remapping layer, applying `Rule`s to the key events before they
+/// are fed to `State::update_key()`.
+pub struct Remapper {
+    rules: Vec<Rule>,
+    undecided: Option<Undecided>,
+    /// Pressed keys, with the key emitted for them.
+    pressed: Vec<(Keycode, Keycode)>,
+    one_shot: Option<OneShot>,
+}
+
+impl Remapper {
+    /// Create a remapper applying the given rules.
+    ///
+    /// If several rules apply to the same key, the first one is used.
+    #[must_use]
+    pub fn new(rules: Vec<Rule>) -> Remapper {
+        Remapper {
+            rules,
+            undecided: None,
+            pressed: Vec::new(),
+            one_shot: None,
+        }
+    }
+
+    /// Create a remapper from a configuration, resolving key names with a
+    /// keymap.
+    ///
+    /// # Errors
+    /// Returns an error on the first invalid line of the configuration.
+    pub fn from_config(keymap: &Keymap, config: &str) -> Result<Remapper, ConfigError> {
+        parse_rules(keymap, config).map(Remapper::new)
+    }
+
+    /// Get the rules of this remapper.
+    #[must_use]
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    fn rule(&self, key: Keycode) -> Option<Rule> {
+        self.rules.iter().copied().find(|rule| match *rule {
+            Rule::Map { from, .. } => from == key,
+            Rule::TapHold { key: k, .. } | Rule::OneShot { key: k } => k == key,
+        })
+    }
+
+    /// Process a physical key event which happened at time `time`.
+    ///
+    /// Returns the remapped key events to feed to `State::update_key()`, in
+    /// order. Events may be delayed while a tap-hold key is undecided.
+    ///
+    /// Key repeats, i.e. presses of a key which is already pressed, are
+    /// dropped: the state counts each press of a modifier key, and would keep
+    /// the modifier set after the single release.
+    pub fn process(
+        &mut self,
+        key: Keycode,
+        direction: KeyDirection,
+        time: Duration,
+    ) -> Vec<KeyEvent> {
+        let mut out = self.poll(time);
+        self.feed(KeyEvent::new(key, direction), time, &mut out);
+        out
+    }
+
+    /// Resolve the undecided tap-hold key as held if its timeout has expired
+    /// at time `now`.
+    ///
+    /// Returns the remapped key events to feed to `State::update_key()`.
+    pub fn poll(&mut self, now: Duration) -> Vec<KeyEvent> {
+        let mut out = Vec::new();
+        if self.undecided.as_ref().is_some_and(|u| u.deadline <= now) {
+            self.resolve(false, &mut out);
+        }
+        out
+    }
+
+    /// Get the time at which the undecided tap-hold key will be held, or
+    /// `None` if there is none.
+    #[must_use]
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.undecided.as_ref().map(|u| u.deadline)
+    }
+
+    fn feed(&mut self, event: KeyEvent, time: Duration, out: &mut Vec<KeyEvent>) {
+        if let Some(undecided) = &mut self.undecided {
+            if event.key == undecided.key && event.direction == KeyDirection::Up {
+                self.resolve(true, out);
+                return;
+            }
+            if event.key == undecided.key {
+                // Key repeat of the tap-hold key.
+                return;
+            }
+            undecided.buffered.push((event, time));
+            let interrupted = event.direction == KeyDirection::Up
+                && undecided
+                    .buffered
+                    .iter()
+                    .any(|(e, _)| e.key == event.key && e.direction == KeyDirection::Down);
+            if undecided.permissive_hold && interrupted {
+                self.resolve(false, out);
+            }
+            return;
+        }
+        match event.direction {
+            KeyDirection::Down => self.press(event.key, time, out),
+            KeyDirection::Up => self.release(event.key, out),
+        }
+    }
+
+    /// Resolve the undecided tap-hold key, and replay the buffered events.
+    fn resolve(&mut self, tap: bool, out: &mut Vec<KeyEvent>) {
+        let Some(undecided) = self.undecided.take() else {
+            return;
+        };
+        if tap {
+            out.push(KeyEvent::new(undecided.tap, KeyDirection::Down));
+            out.push(KeyEvent::new(undecided.tap, KeyDirection::Up));
+            if let Some(OneShot::Armed(one_shot)) = self.one_shot {
+                self.one_shot = None;
+                out.push(KeyEvent::new(one_shot, KeyDirection::Up));
+            }
+        } else {
+            self.pressed.push((undecided.key, undecided.hold));
+            out.push(KeyEvent::new(undecided.hold, KeyDirection::Down));
+        }
+        for (event, time) in undecided.buffered {
+            self.feed(event, time, out);
+        }
+    }
+
+    fn press(&mut self, key: Keycode, time: Duration, out: &mut Vec<KeyEvent>) {
+        if self.pressed.iter().any(|&(k, _)| k == key) {
+            // Key repeat.
+            return;
+        }
+        match self.rule(key) {
+            Some(Rule::TapHold {
+                tap,
+                hold,
+                timeout,
+                permissive_hold,
+                ..
+            }) => {
+                self.undecided = Some(Undecided {
+                    key,
+                    tap,
+                    hold,
+                    deadline: time + timeout,
+                    permissive_hold,
+                    buffered: Vec::new(),
+                });
+            }
+            Some(Rule::OneShot { .. }) => {
+                if let Some(OneShot::Armed(armed)) = self.one_shot {
+                    // Tapping the one-shot key again cancels it, and another
+                    // one-shot key replaces it.
+                    out.push(KeyEvent::new(armed, KeyDirection::Up));
+                    if armed == key {
+                        self.one_shot = Some(OneShot::Cancelled(key));
+                        return;
+                    }
+                }
+                self.one_shot = Some(OneShot::Pressed(key));
+                self.pressed.push((key, key));
+                out.push(KeyEvent::new(key, KeyDirection::Down));
+            }
+            rule => {
+                let emitted = match rule {
+                    Some(Rule::Map { to, .. }) => to,
+                    _ => key,
+                };
+                match self.one_shot {
+                    Some(OneShot::Pressed(_)) => self.one_shot = None,
+                    Some(OneShot::Armed(one_shot)) => {
+                        self.one_shot = Some(OneShot::Applied(one_shot, key));
+                    }
+                    _ => {}
+                }
+                self.pressed.push((key, emitted));
+                out.push(KeyEvent::new(emitted, KeyDirection::Down));
+            }
+        }
+    }
+
+    fn release(&mut self, key: Keycode, out: &mut Vec<KeyEvent>) {
+        if self.one_shot == Some(OneShot::Cancelled(key)) {
+            self.one_shot = None;
+            return;
+        }
+        let Some(pos) = self.pressed.iter().position(|&(k, _)| k == key) else {
+            return;
+        };
+        let (_, emitted) = self.pressed.remove(pos);
+        match self.one_shot {
+            Some(OneShot::Pressed(one_shot)) if one_shot == key => {
+                // Tapped: keep it pressed for the next key.
+                self.one_shot = Some(OneShot::Armed(one_shot));
+            }
+            Some(OneShot::Applied(one_shot, applied)) if applied == key => {
+                self.one_shot = None;
+                out.push(KeyEvent::new(emitted, KeyDirection::Up));
+                out.push(KeyEvent::new(one_shot, KeyDirection::Up));
+            }
+            _ => out.push(KeyEvent::new(emitted, KeyDirection::Up)),
+        }
+    }
+}
+
+#[test]
+fn remapper_resolves_tap_hold_keys() {
+    let (caps, esc, ctrl, a) = (
+        Keycode::new(66),
+        Keycode::new(9),
+        Keycode::new(37),
+        Keycode::new(38),
+    );
+    let mut remapper = Remapper::new(vec![Rule::TapHold {
+        key: caps,
+        tap: esc,
+        hold: ctrl,
+        timeout: Duration::from_millis(200),
+        permissive_hold: true,
+    }]);
+    let mut process =
+        |key, direction, ms| remapper.process(key, direction, Duration::from_millis(ms));
+    let down = |key| KeyEvent::new(key, KeyDirection::Down);
+    let up = |key| KeyEvent::new(key, KeyDirection::Up);
+    // Tap.
+    assert!(process(caps, KeyDirection::Down, 1000).is_empty());
+    assert_eq!(process(caps, KeyDirection::Up, 1100), [down(esc), up(esc)]);
+    // Permissive hold.
+    assert!(process(caps, KeyDirection::Down, 2000).is_empty());
+    assert!(process(a, KeyDirection::Down, 2010).is_empty());
+    assert_eq!(
+        process(a, KeyDirection::Up, 2020),
+        [down(ctrl), down(a), up(a)]
+    );
+    assert_eq!(process(caps, KeyDirection::Up, 2030), [up(ctrl)]);
+    // Hold after the timeout.
+    assert!(process(caps, KeyDirection::Down, 3000).is_empty());
+    assert_eq!(process(a, KeyDirection::Down, 3300), [down(ctrl), down(a)]);
+}
+
+#[test]
+fn remapper_applies_one_shot_keys() {
+    let (shift, a) = (Keycode::new(50), Keycode::new(38));
+    let mut remapper = Remapper::new(vec![Rule::OneShot { key: shift }]);
+    let mut process = |key, direction| remapper.process(key, direction, Duration::ZERO);
+    let down = |key| KeyEvent::new(key, KeyDirection::Down);
+    let up = |key| KeyEvent::new(key, KeyDirection::Up);
+    assert_eq!(process(shift, KeyDirection::Down), [down(shift)]);
+    assert!(process(shift, KeyDirection::Up).is_empty());
+    assert_eq!(process(a, KeyDirection::Down), [down(a)]);
+    assert_eq!(process(a, KeyDirection::Up), [up(a), up(shift)]);
+    assert_eq!(process(a, KeyDirection::Down), [down(a)]);
+    assert_eq!(process(a, KeyDirection::Up), [up(a)]);
+}
+
+#[test]
+fn remapper_drops_key_repeats() {
+    use super::{
+        Context, State, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS, MOD_NAME_CTRL,
+        STATE_MODS_EFFECTIVE,
+    };
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let mut state = State::new(&keymap);
+    let mut remapper = Remapper::from_config(&keymap, "tap-hold CAPS ESC LCTL").unwrap();
+    let caps = keymap.key_by_name("CAPS").unwrap();
+    let mut process = |direction, ms| {
+        let events = remapper.process(caps, direction, Duration::from_millis(ms));
+        for event in &events {
+            state.update_key(event.key, event.direction);
+        }
+        events.len()
+    };
+    assert_eq!(process(KeyDirection::Down, 0), 0);
+    // The first repeat comes after the timeout, and holds the key.
+    assert_eq!(process(KeyDirection::Down, 300), 1);
+    assert_eq!(process(KeyDirection::Down, 330), 0);
+    assert_eq!(process(KeyDirection::Down, 360), 0);
+    assert_eq!(process(KeyDirection::Up, 400), 1);
+    assert!(!state.mod_name_is_active(MOD_NAME_CTRL, STATE_MODS_EFFECTIVE));
+}
@@ -0,0 +1,320 @@
+use super::{
+    KeyDirection, Keycode, Keymap, ModMask, State, StateSnapshot, STATE_LAYOUT_LOCKED,
+    STATE_MODS_LOCKED,
+};
+
+/// Events emitted by `SeatKeyboard`, to be forwarded to the clients.
+#[derive(Clone)]
+pub enum SeatEvent {
+    /// The active keymap changed. It must be sent to the clients before the
+    /// modifiers which follow.
+    KeymapChanged(Keymap),
+    /// The merged modifiers and layouts changed, as to be passed to
+    /// `State::update_mask()` by the clients.
+    ModifiersChanged(StateSnapshot),
+}
+
+struct Device<D> {
+    id: D,
+    state: State,
+}
+
+/// Keyboard state of a seat with several physical keyboards.
+///
+/// Each device has its own `State`, possibly with its own keymap, while the
+/// clients see a single keyboard. The state presented to them uses the
+/// keymap of the active device, i.e. the device on which a key was last
+/// pressed, and merges the states of the devices:
+///
+/// - the depressed and latched modifiers of all devices are combined, so
+///   that Shift held on one keyboard applies to the keys of another;
+/// - the locked modifiers and the locked layout are shared by all devices,
+///   so that Caps Lock toggled on one keyboard is locked on all of them;
+/// - the depressed and latched layouts are those of the active device.
+///
+/// Modifiers are matched by name between devices with different keymaps.
+///
+/// Devices are identified by an arbitrary identifier `D`, e.g. the handle of
+/// the input device in the compositor.
+pub struct SeatKeyboard<D> {
+    devices: Vec<Device<D>>,
+    active: usize,
+    sent_keymap: Option<Keymap>,
+    sent_mods: Option<StateSnapshot>,
+}
+
+impl<D: PartialEq> Default for SeatKeyboard<D> {
+    fn default() -> SeatKeyboard<D> {
+        SeatKeyboard::new()
+    }
+}
+
+/// Convert a modifier mask of a keymap to the mask of the same modifiers in
+/// another keymap, dropping the modifiers it does not have.
+fn translate_mods(mask: ModMask, from: &Keymap, to: &Keymap) -> ModMask {
+    if from == to {
+        return mask;
+    }
+    from.mask_to_names(mask)
+        .map(|name| to.mod_get_index(name))
+        .filter(|&idx| idx < ModMask::BITS)
+        .fold(0, |mask, idx| mask | (1 << idx))
+}
+
+impl<D: PartialEq> SeatKeyboard<D> {
+    /// Create a seat keyboard without devices.
+    #[must_use]
+    pub fn new() -> SeatKeyboard<D> {
+        SeatKeyboard {
+            devices: Vec::new(),
+            active: 0,
+            sent_keymap: None,
+            sent_mods: None,
+        }
+    }
+
+    fn position(&self, id: &D) -> Option<usize> {
+        self.devices.iter().position(|device| device.id == *id)
+    }
+
+    /// Add a device with a given keymap, or replace the keymap of an
+    /// existing device.
+    ///
+    /// The device starts with the locks shared by the other devices. The
+    /// first device added becomes the active device.
+    pub fn add_device(&mut self, id: D, keymap: &Keymap) -> Vec<SeatEvent> {
+        let mut state = State::new(keymap);
+        if let Some(active) = self.devices.get(self.active) {
+            Self::copy_locks(&active.state, &mut state);
+        }
+        match self.position(&id) {
+            Some(pos) => self.devices[pos].state = state,
+            None => self.devices.push(Device { id, state }),
+        }
+        self.events()
+    }
+
+    /// Remove a device.
+    ///
+    /// If it was the active device, the first remaining device becomes the
+    /// active device.
+    pub fn remove_device(&mut self, id: &D) -> Vec<SeatEvent> {
+        let Some(pos) = self.position(id) else {
+            return Vec::new();
+        };
+        self.devices.remove(pos);
+        if pos < self.active {
+            self.active -= 1;
+        } else if pos == self.active {
+            self.active = 0;
+        }
+        self.events()
+    }
+
+    /// Get the identifiers of the devices.
+    pub fn devices(&self) -> impl Iterator<Item = &D> {
+        self.devices.iter().map(|device| &device.id)
+    }
+
+    /// Get the keyboard state of a device.
+    #[must_use]
+    pub fn device_state(&self, id: &D) -> Option<&State> {
+        self.position(id).map(|pos| &self.devices[pos].state)
+    }
+
+    /// Get the identifier of the active device, or `None` if there is no
+    /// device.
+    #[must_use]
+    pub fn active_device(&self) -> Option<&D> {
+        self.devices.get(self.active).map(|device| &device.id)
+    }
+
+    /// Get the keymap presented to the clients, i.e. the keymap of the
+    /// active device, or `None` if there is no device.
+    #[must_use]
+    pub fn active_keymap(&self) -> Option<Keymap> {
+        self.devices
+            .get(self.active)
+            .map(|device| device.state.get_keymap())
+    }
+
+    /// Update the state of a device to reflect a given key being pressed or
+    /// released.
+    ///
+    /// A key press makes the device the active device. Locks changed by the
+    /// key are applied to all devices.
+    ///
+    /// Returns the events to forward to the clients, if the active keymap or
+    /// the merged state changed.
+    pub fn update_key(&mut self, id: &D, key: Keycode, direction: KeyDirection) -> Vec<SeatEvent> {
+        let Some(pos) = self.position(id) else {
+            return Vec::new();
+        };
+        if direction == KeyDirection::Down {
+            self.active = pos;
+        }
+        let changes = self.devices[pos]
+            .state
+            .update_key_with_changes(key, direction);
+        if changes.contains(STATE_MODS_LOCKED | STATE_LAYOUT_LOCKED) {
+            let (before, after) = self.devices.split_at_mut(pos);
+            let (device, after) = after.split_first_mut().unwrap();
+            for other in before.iter_mut().chain(after) {
+                Self::copy_locks(&device.state, &mut other.state);
+            }
+        }
+        self.events()
+    }
+
+    /// Apply the locked modifiers and layout of a state to another one.
+    fn copy_locks(from: &State, to: &mut State) {
+        let source = from.snapshot();
+        let mut snapshot = to.snapshot();
+        snapshot.locked_mods =
+            translate_mods(source.locked_mods, &from.get_keymap(), &to.get_keymap());
+        snapshot.locked_layout = source.locked_layout;
+        to.restore(&snapshot);
+    }
+
+    /// Get the merged state presented to the clients, in terms of the active
+    /// keymap, or `None` if there is no device.
+    #[must_use]
+    pub fn serialize(&self) -> Option<StateSnapshot> {
+        let active = self.devices.get(self.active)?;
+        let keymap = active.state.get_keymap();
+        let mut merged = active.state.snapshot();
+        for device in &self.devices {
+            let snapshot = device.state.snapshot();
+            let from = device.state.get_keymap();
+            merged.depressed_mods |= translate_mods(snapshot.depressed_mods, &from, &keymap);
+            merged.latched_mods |= translate_mods(snapshot.latched_mods, &from, &keymap);
+        }
+        Some(merged)
+    }
+
+    /// Get the events for the changes since the last events.
+    fn events(&mut self) -> Vec<SeatEvent> {
+        let mut events = Vec::new();
+        let keymap = self.active_keymap();
+        if keymap != self.sent_keymap {
+            self.sent_keymap.clone_from(&keymap);
+            self.sent_mods = None;
+            events.extend(keymap.map(SeatEvent::KeymapChanged));
+        }
+        let mods = self.serialize();
+        if mods.is_some() && mods != self.sent_mods {
+            self.sent_mods = mods;
+            events.extend(mods.map(SeatEvent::ModifiersChanged));
+        }
+        events
+    }
+}
+
+#[test]
+fn seat_keyboard_shares_locks() {
+    use super::{
+        Context, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS, MOD_NAME_CAPS, MOD_NAME_SHIFT,
+        STATE_MODS_DEPRESSED,
+    };
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let names = |layouts| {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layouts,
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let (us, de) = (names("us"), names("de"));
+    let caps = us.key_by_name("CAPS").unwrap();
+    let shift = us.key_by_name("LFSH").unwrap();
+    let lock = 1 << us.mod_get_index(MOD_NAME_CAPS);
+    let mut seat = SeatKeyboard::new();
+    assert!(matches!(
+        seat.add_device(1, &us)[..],
+        [SeatEvent::KeymapChanged(_), SeatEvent::ModifiersChanged(_)]
+    ));
+    seat.add_device(2, &de);
+    assert_eq!(seat.active_device(), Some(&1));
+
+    // Caps Lock pressed on the second device locks the first one too.
+    let events = seat.update_key(&2, caps, KeyDirection::Down);
+    let [SeatEvent::KeymapChanged(keymap), SeatEvent::ModifiersChanged(_)] = &events[..] else {
+        panic!("unexpected events");
+    };
+    assert!(keymap.ptr_eq(&de));
+    seat.update_key(&2, caps, KeyDirection::Up);
+    let locked = |seat: &SeatKeyboard<i32>, id| {
+        seat.device_state(&id)
+            .unwrap()
+            .serialize_mods(STATE_MODS_LOCKED)
+    };
+    assert_eq!((locked(&seat, 1), locked(&seat, 2)), (lock, lock));
+
+    // A device added later starts with the shared locks.
+    seat.add_device(3, &us);
+    assert_eq!(locked(&seat, 3), lock);
+
+    // Depressed modifiers are merged, but not copied to the other devices.
+    seat.update_key(&1, shift, KeyDirection::Down);
+    let merged = seat.serialize().unwrap();
+    assert_eq!(merged.depressed_mods, 1 << us.mod_get_index(MOD_NAME_SHIFT));
+    assert_eq!(merged.locked_mods, lock);
+    let depressed = seat
+        .device_state(&2)
+        .unwrap()
+        .serialize_mods(STATE_MODS_DEPRESSED);
+    assert_eq!(depressed, 0);
+
+    seat.update_key(&1, caps, KeyDirection::Down);
+    seat.update_key(&1, caps, KeyDirection::Up);
+    assert_eq!(
+        (locked(&seat, 1), locked(&seat, 2), locked(&seat, 3)),
+        (0, 0, 0)
+    );
+    assert!(seat
+        .remove_device(&1)
+        .iter()
+        .any(|event| matches!(event, SeatEvent::KeymapChanged(_))));
+    assert_eq!(seat.active_device(), Some(&2));
+}
+
+#[test]
+fn seat_keyboard_identical_keymaps() {
+    use super::{Context, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS, MOD_NAME_SHIFT};
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let us = || {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            "us",
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let (first, second) = (us(), us());
+    let shift = first.key_by_name("LFSH").unwrap();
+    let mut seat = SeatKeyboard::new();
+    seat.add_device(1, &first);
+    seat.add_device(2, &second);
+
+    // Switching between devices with identical keymaps keeps the keymap.
+    let events = seat.update_key(&2, shift, KeyDirection::Down);
+    let [SeatEvent::ModifiersChanged(mods)] = &events[..] else {
+        panic!("unexpected events");
+    };
+    assert_eq!(
+        mods.depressed_mods,
+        1 << first.mod_get_index(MOD_NAME_SHIFT)
+    );
+    assert_eq!(seat.active_device(), Some(&2));
+}
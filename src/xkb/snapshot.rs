@@ -0,0 +1,271 @@
+use super::{
+    Keymap, LayoutIndex, ModMask, State, StateChanges, LAYOUT_INVALID, STATE_LAYOUT_DEPRESSED,
+    STATE_LAYOUT_LATCHED, STATE_LAYOUT_LOCKED, STATE_MODS_DEPRESSED, STATE_MODS_LATCHED,
+    STATE_MODS_LOCKED,
+};
+
+/// A snapshot of the modifiers and layouts of a keyboard state.
+///
+/// The fields hold the values produced by `State::serialize_mods()` and
+/// `State::serialize_layout()`, i.e. the arguments of `State::update_mask()`.
+/// This allows to hand a keyboard state over to another process (e.g. from
+/// a session to a lock screen), so that locked modifiers like Caps Lock and
+/// Num Lock and the active layout carry over.
+///
+/// A snapshot is only meaningful for the keymap it was taken with. Use
+/// `NamedStateSnapshot` if the keymap may change in between.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateSnapshot {
+    pub depressed_mods: ModMask,
+    pub latched_mods: ModMask,
+    pub locked_mods: ModMask,
+    pub depressed_layout: LayoutIndex,
+    pub latched_layout: LayoutIndex,
+    pub locked_layout: LayoutIndex,
+}
+
+/// A snapshot of the modifiers and layouts of a keyboard state, which
+/// refers to modifiers and to the locked layout by name rather than by
+/// index.
+///
+/// Unlike `StateSnapshot`, it can be restored into a state using another
+/// keymap than the one it was taken with. Modifiers and layout unknown to
+/// the new keymap are ignored.
+///
+/// Real modifiers which a virtual modifier is mapped to are recorded by the
+/// name of the virtual modifier, e.g. `NumLock` rather than `Mod2`, so that
+/// they are restored into the real modifiers the new keymap maps it to.
+///
+/// The depressed and latched layouts are relative values, and are kept
+/// as such.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NamedStateSnapshot {
+    pub depressed_mods: Vec<String>,
+    pub latched_mods: Vec<String>,
+    pub locked_mods: Vec<String>,
+    pub depressed_layout: LayoutIndex,
+    pub latched_layout: LayoutIndex,
+    /// Name of the locked layout, or `None` if the layout has no name.
+    pub locked_layout: Option<String>,
+}
+
+/// The mask of the real modifiers, which come before the virtual modifiers
+/// in every keymap.
+const REAL_MODS: ModMask = 0xff;
+
+impl StateSnapshot {
+    /// Convert this snapshot to its name-based form, resolving indices with
+    /// the keymap the snapshot was taken with.
+    #[must_use]
+    pub fn to_named(&self, keymap: &Keymap) -> NamedStateSnapshot {
+        let names = |mask: ModMask| -> Vec<String> {
+            let virtual_mods: Vec<&str> = keymap
+                .mask_to_names(!REAL_MODS)
+                .filter(|name| {
+                    let mapping = keymap.names_to_mask(&[name]).unwrap_or(0);
+                    mapping != 0 && mask & mapping == mapping
+                })
+                .collect();
+            let mapped = keymap.names_to_mask(&virtual_mods).unwrap_or(0);
+            keymap
+                .mask_to_names(mask & REAL_MODS & !mapped)
+                .chain(virtual_mods)
+                .map(str::to_owned)
+                .collect()
+        };
+        let locked_layout = match keymap.layout_get_name(self.locked_layout) {
+            "" => None,
+            name => Some(name.to_owned()),
+        };
+        NamedStateSnapshot {
+            depressed_mods: names(self.depressed_mods),
+            latched_mods: names(self.latched_mods),
+            locked_mods: names(self.locked_mods),
+            depressed_layout: self.depressed_layout,
+            latched_layout: self.latched_layout,
+            locked_layout,
+        }
+    }
+}
+
+impl NamedStateSnapshot {
+    /// Convert this snapshot to its index-based form for a given keymap.
+    #[must_use]
+    pub fn to_indexed(&self, keymap: &Keymap) -> StateSnapshot {
+        let mask = |names: &[String]| -> ModMask {
+            names
+                .iter()
+                .filter_map(|name| keymap.names_to_mask(&[name.as_str()]))
+                .fold(0, |mask, mods| mask | mods)
+        };
+        let locked_layout = self
+            .locked_layout
+            .as_ref()
+            .map(|name| keymap.layout_get_index(name.as_str()))
+            .filter(|&idx| idx != LAYOUT_INVALID)
+            .unwrap_or(0);
+        StateSnapshot {
+            depressed_mods: mask(&self.depressed_mods),
+            latched_mods: mask(&self.latched_mods),
+            locked_mods: mask(&self.locked_mods),
+            depressed_layout: self.depressed_layout,
+            latched_layout: self.latched_layout,
+            locked_layout,
+        }
+    }
+}
+
+impl State {
+    /// Take a snapshot of the modifiers and layouts of this state.
+    #[must_use]
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            depressed_mods: self.serialize_mods(STATE_MODS_DEPRESSED),
+            latched_mods: self.serialize_mods(STATE_MODS_LATCHED),
+            locked_mods: self.serialize_mods(STATE_MODS_LOCKED),
+            depressed_layout: self.serialize_layout(STATE_LAYOUT_DEPRESSED),
+            latched_layout: self.serialize_layout(STATE_LAYOUT_LATCHED),
+            locked_layout: self.serialize_layout(STATE_LAYOUT_LOCKED),
+        }
+    }
+
+    /// Take a name-based snapshot of the modifiers and layouts of this state.
+    #[must_use]
+    pub fn snapshot_named(&self) -> NamedStateSnapshot {
+        self.snapshot().to_named(&self.get_keymap())
+    }
+
+    /// Restore the modifiers and layouts of a snapshot into this state,
+    /// through `update_mask()`.
+    ///
+    /// The same caveats as `update_mask()` apply: this should not be mixed
+    /// with `update_key()` on a state which tracks key events.
+    pub fn restore(&mut self, snapshot: &StateSnapshot) -> StateChanges {
+        self.update_mask_with_changes(
+            snapshot.depressed_mods,
+            snapshot.latched_mods,
+            snapshot.locked_mods,
+            snapshot.depressed_layout,
+            snapshot.latched_layout,
+            snapshot.locked_layout,
+        )
+    }
+
+    /// Restore the modifiers and layouts of a name-based snapshot into this
+    /// state, resolving the names with the keymap of this state.
+    pub fn restore_named(&mut self, snapshot: &NamedStateSnapshot) -> StateChanges {
+        let snapshot = snapshot.to_indexed(&self.get_keymap());
+        self.restore(&snapshot)
+    }
+}
+
+#[test]
+fn state_snapshots() {
+    use super::{
+        Context, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS, KEYMAP_FORMAT_TEXT_V1, MOD_NAME_CAPS,
+        MOD_NAME_MOD3, MOD_NAME_NUM, MOD_NAME_SHIFT, STATE_LAYOUT_EFFECTIVE, VMOD_NAME_NUM,
+    };
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let names = |layouts| {
+        Keymap::new_from_names(
+            &context,
+            "evdev",
+            "pc105",
+            layouts,
+            "",
+            None,
+            KEYMAP_COMPILE_NO_FLAGS,
+        )
+        .unwrap()
+    };
+    let (us_de, de_us) = (names("us,de"), names("de,us"));
+    let mask = |keymap: &Keymap, name| 1 << keymap.mod_get_index(name);
+    let mut state = State::new(&us_de);
+    let locked = mask(&us_de, MOD_NAME_CAPS) | mask(&us_de, MOD_NAME_NUM);
+    state.update_mask(mask(&us_de, MOD_NAME_SHIFT), 0, locked, 0, 0, 1);
+
+    let snapshot = state.snapshot();
+    assert_eq!(snapshot.locked_mods, locked);
+    assert_eq!(snapshot.locked_layout, 1);
+    let mut other = State::new(&us_de);
+    let changes = other.restore(&snapshot);
+    assert_eq!(changes.new.locked_mods, locked);
+    assert_eq!(other.snapshot(), snapshot);
+    assert!(other.restore(&snapshot).is_empty());
+
+    // The locked layout is restored by name into a keymap with another
+    // layout order.
+    let named = state.snapshot_named();
+    assert_eq!(named.locked_mods, [MOD_NAME_CAPS, VMOD_NAME_NUM]);
+    assert_eq!(named.locked_layout.as_deref(), Some("German"));
+    let mut other = State::new(&de_us);
+    other.restore_named(&named);
+    assert_eq!(other.serialize_layout(STATE_LAYOUT_EFFECTIVE), 0);
+    assert_eq!(other.serialize_mods(STATE_MODS_LOCKED), locked);
+    assert_eq!(other.snapshot_named(), named);
+
+    // Unknown names are ignored.
+    let mut unknown = named.clone();
+    unknown.locked_mods.push("Foo".into());
+    unknown.locked_layout = Some("Klingon".into());
+    assert_eq!(unknown.to_indexed(&de_us).locked_mods, locked);
+    assert_eq!(unknown.to_indexed(&de_us).locked_layout, 0);
+
+    // Num Lock is restored into the real modifier another keymap maps it to.
+    let mod3 = Keymap::new_from_string(
+        &context,
+        r#"xkb_keymap {
+            xkb_keycodes { include "evdev" };
+            xkb_types { include "complete" };
+            xkb_compat { include "complete" };
+            xkb_symbols {
+                include "us"
+                key <CAPS> { [ Caps_Lock ] };
+                key <NMLK> { [ Num_Lock ] };
+                modifier_map Lock { <CAPS> };
+                modifier_map Mod3 { <NMLK> };
+            };
+        };"#
+        .into(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let mut other = State::new(&mod3);
+    other.restore_named(&named);
+    assert_eq!(
+        other.serialize_mods(STATE_MODS_LOCKED),
+        mask(&mod3, MOD_NAME_CAPS) | mask(&mod3, MOD_NAME_MOD3)
+    );
+    assert_eq!(other.snapshot_named().locked_mods, named.locked_mods);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn state_snapshot_serde() {
+    let snapshot = StateSnapshot {
+        depressed_mods: 1,
+        locked_mods: 18,
+        locked_layout: 1,
+        ..StateSnapshot::default()
+    };
+    let json = serde_json::to_string(&snapshot).unwrap();
+    assert_eq!(
+        serde_json::from_str::<StateSnapshot>(&json).unwrap(),
+        snapshot
+    );
+
+    let named = NamedStateSnapshot {
+        locked_mods: vec!["Lock".into(), "Mod2".into()],
+        locked_layout: Some("German".into()),
+        ..NamedStateSnapshot::default()
+    };
+    let json = serde_json::to_string(&named).unwrap();
+    assert_eq!(
+        serde_json::from_str::<NamedStateSnapshot>(&json).unwrap(),
+        named
+    );
+}
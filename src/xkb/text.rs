@@ -0,0 +1,1228 @@
+//! Parser for the XKB text v1 keymap format.
+//!
+//! This module parses keymaps in the format produced by
+//! `Keymap::get_as_string(KEYMAP_FORMAT_TEXT_V1)` into a typed syntax tree,
+//! giving access to the information the C API does not expose, such as key
+//! types, compatibility interprets, indicator maps and explicit actions.
+//!
+//! Every node of the tree records its `Span` in the source text. The tree
+//! implements `Display`, which writes it back as keymap text accepted by
+//! `Keymap::new_from_string()`.
+//!
+//! The parser handles the fully resolved keymaps written by libxkbcommon,
+//! and the general statement and expression syntax of XKB files, but does
+//! not resolve `include` statements.
+
+use super::Keymap;
+use super::KEYMAP_FORMAT_TEXT_V1;
+use std::error::Error;
+use std::fmt;
+
+/// Range of bytes of a syntax tree node in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    #[must_use]
+    pub fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    /// Get the smallest span containing both spans.
+    #[must_use]
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// Error encountered while parsing keymap text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub span: Span,
+    /// Line of the error, starting at 1.
+    pub line: usize,
+    /// Column of the error in characters, starting at 1.
+    pub column: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+/// A keymap file: `xkb_keymap { ... };`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeymapFile {
+    /// Flags preceding the keymap, e.g. `default`.
+    pub flags: Vec<String>,
+    pub name: Option<String>,
+    pub sections: Vec<Section>,
+    pub span: Span,
+}
+
+/// Kind of a keymap section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SectionKind {
+    Keycodes,
+    Types,
+    Compat,
+    Symbols,
+    Geometry,
+}
+
+impl SectionKind {
+    fn from_keyword(keyword: &str) -> Option<SectionKind> {
+        Some(match keyword {
+            "xkb_keycodes" => SectionKind::Keycodes,
+            "xkb_types" => SectionKind::Types,
+            "xkb_compatibility" | "xkb_compatibility_map" | "xkb_compat" | "xkb_compat_map" => {
+                SectionKind::Compat
+            }
+            "xkb_symbols" => SectionKind::Symbols,
+            "xkb_geometry" => SectionKind::Geometry,
+            _ => return None,
+        })
+    }
+
+    /// Get the keyword starting sections of this kind.
+    #[must_use]
+    pub fn keyword(self) -> &'static str {
+        match self {
+            SectionKind::Keycodes => "xkb_keycodes",
+            SectionKind::Types => "xkb_types",
+            SectionKind::Compat => "xkb_compatibility",
+            SectionKind::Symbols => "xkb_symbols",
+            SectionKind::Geometry => "xkb_geometry",
+        }
+    }
+}
+
+/// A keymap section, e.g. `xkb_types "name" { ... };`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// Flags preceding the section, e.g. `partial alphanumeric_keys`.
+    pub flags: Vec<String>,
+    pub kind: SectionKind,
+    pub name: Option<String>,
+    pub statements: Vec<Statement>,
+    pub span: Span,
+}
+
+/// A statement of a section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatementKind {
+    /// `include "file"`, or another merge mode instead of `include`.
+    Include { merge: String, file: String },
+    /// Variable definition, e.g. `minimum = 8;` or
+    /// `interpret.repeat= False;`.
+    Var(VarDef),
+    /// `virtual_modifiers NumLock,Alt;`.
+    VirtualModifiers(Vec<VarDef>),
+    /// Keycode definition: `<AE01> = 10;`.
+    Keycode { name: String, value: Expr },
+    /// Keycode alias: `alias <ALGR> = <RALT>;`.
+    Alias { alias: String, real: String },
+    /// LED name: `indicator 1 = "Caps Lock";`.
+    IndicatorName {
+        index: u32,
+        name: String,
+        is_virtual: bool,
+    },
+    /// Key type: `type "TWO_LEVEL" { ... };`.
+    KeyType { name: String, body: Vec<VarDef> },
+    /// Symbol interpretation: `interpret Num_Lock+AnyOf(all) { ... };`.
+    Interpret {
+        keysym: Expr,
+        predicate: Option<Expr>,
+        body: Vec<VarDef>,
+    },
+    /// LED map: `indicator "Caps Lock" { ... };`.
+    IndicatorMap { name: String, body: Vec<VarDef> },
+    /// Key symbols: `key <AE01> { ... };`.
+    Key { name: String, body: Vec<VarDef> },
+    /// Modifier map: `modifier_map Shift { <LFSH>, <RTSH> };`.
+    ModifierMap { modifier: String, keys: Vec<Expr> },
+    /// Group compatibility map: `group 2 = AltGr;`.
+    GroupCompat { group: u32, value: Expr },
+}
+
+/// A variable definition, `name = value`.
+///
+/// `name` is an `Ident`, `FieldRef` or `ArrayRef` expression. It is `None`
+/// for a bare value, such as a boolean flag (`clearLocks`, `!same`) or the
+/// list of symbols of a key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VarDef {
+    pub name: Option<Expr>,
+    pub value: Expr,
+    pub span: Span,
+}
+
+impl VarDef {
+    /// Get the field name of the definition, e.g. `symbols` for
+    /// `symbols[Group1]= [ a, A ]`.
+    #[must_use]
+    pub fn field(&self) -> Option<&str> {
+        match &self.name.as_ref()?.kind {
+            ExprKind::Ident(field)
+            | ExprKind::FieldRef { field, .. }
+            | ExprKind::ArrayRef { field, .. } => Some(field),
+            _ => None,
+        }
+    }
+
+    /// Get the index of the definition, e.g. `Group1` for
+    /// `symbols[Group1]= [ a, A ]`.
+    #[must_use]
+    pub fn index(&self) -> Option<&Expr> {
+        match &self.name.as_ref()?.kind {
+            ExprKind::ArrayRef { index, .. } => Some(index),
+            _ => None,
+        }
+    }
+}
+
+/// An expression.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExprKind {
+    /// Identifier, e.g. a keysym, modifier or flag name.
+    Ident(String),
+    /// Integer, written in hexadecimal if `hex`.
+    Integer {
+        value: i64,
+        hex: bool,
+    },
+    Float(f64),
+    String(String),
+    /// Key name, without the angle brackets.
+    KeyName(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// Function call or action, e.g. `SetMods(modifiers=Shift,clearLocks)`.
+    Call {
+        name: String,
+        args: Vec<VarDef>,
+    },
+    /// `elem.field`.
+    FieldRef {
+        elem: String,
+        field: String,
+    },
+    /// `field[index]` or `elem.field[index]`.
+    ArrayRef {
+        elem: Option<String>,
+        field: String,
+        index: Box<Expr>,
+    },
+    /// `[ a, b ]`.
+    List(Vec<Expr>),
+    /// `{ a, b }`, e.g. several keysyms on one level.
+    Braced(Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    Negate,
+    Plus,
+    Not,
+    Invert,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl BinaryOp {
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Add | BinaryOp::Subtract => 1,
+            BinaryOp::Multiply | BinaryOp::Divide => 2,
+        }
+    }
+}
+
+impl Expr {
+    /// Get the name of an identifier expression.
+    #[must_use]
+    pub fn as_ident(&self) -> Option<&str> {
+        match &self.kind {
+            ExprKind::Ident(ident) => Some(ident),
+            _ => None,
+        }
+    }
+
+    /// Get the value of an integer expression, including negated integers.
+    #[must_use]
+    pub fn as_integer(&self) -> Option<i64> {
+        match &self.kind {
+            ExprKind::Integer { value, .. } => Some(*value),
+            ExprKind::Unary(UnaryOp::Negate, expr) => expr.as_integer().map(|v| -v),
+            ExprKind::Unary(UnaryOp::Plus, expr) => expr.as_integer(),
+            _ => None,
+        }
+    }
+
+    /// Get the value of a string expression.
+    #[must_use]
+    pub fn as_string(&self) -> Option<&str> {
+        match &self.kind {
+            ExprKind::String(string) => Some(string),
+            _ => None,
+        }
+    }
+}
+
+/// Parse keymap text.
+///
+/// # Errors
+/// Returns the first syntax error of the text.
+pub fn parse(text: &str) -> Result<KeymapFile, ParseError> {
+    let tokens = lex(text)?;
+    let mut parser = Parser {
+        text,
+        tokens,
+        pos: 0,
+    };
+    let file = parser.keymap_file()?;
+    if parser.peek() != &Token::Eof {
+        return Err(parser.error("expected end of file"));
+    }
+    Ok(file)
+}
+
+/// Parse the text form of a keymap.
+///
+/// # Errors
+/// Returns an error if the text written by libxkbcommon cannot be parsed.
+pub fn parse_keymap(keymap: &Keymap) -> Result<KeymapFile, ParseError> {
+    parse(&keymap.get_as_string(KEYMAP_FORMAT_TEXT_V1))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Integer(i64, bool),
+    Float(f64),
+    String(String),
+    KeyName(String),
+    Punct(char),
+    Eof,
+}
+
+fn error_at(text: &str, span: Span, message: impl Into<String>) -> ParseError {
+    let before = &text[..span.start.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit('\n')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .count()
+        + 1;
+    ParseError {
+        message: message.into(),
+        span,
+        line,
+        column,
+    }
+}
+
+fn is_ident_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+fn lex(text: &str) -> Result<Vec<(Token, Span)>, ParseError> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    while pos < bytes.len() {
+        let start = pos;
+        let c = bytes[pos];
+        if c.is_ascii_whitespace() {
+            pos += 1;
+            continue;
+        }
+        if c == b'#' || text[pos..].starts_with("//") {
+            pos = text[pos..].find('\n').map_or(bytes.len(), |end| pos + end);
+            continue;
+        }
+        if text[pos..].starts_with("/*") {
+            pos = match text[pos + 2..].find("*/") {
+                Some(end) => pos + 2 + end + 2,
+                None => {
+                    return Err(error_at(
+                        text,
+                        Span::new(start, bytes.len()),
+                        "unterminated comment",
+                    ))
+                }
+            };
+            continue;
+        }
+        let token = if c.is_ascii_digit() {
+            while pos < bytes.len() && (is_ident_char(bytes[pos]) || bytes[pos] == b'.') {
+                pos += 1;
+            }
+            let word = &text[start..pos];
+            if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                match i64::from_str_radix(hex, 16) {
+                    Ok(value) => Token::Integer(value, true),
+                    Err(_) => Token::Ident(word.to_owned()),
+                }
+            } else if let Ok(value) = word.parse() {
+                Token::Integer(value, false)
+            } else if let Ok(value) = word.parse() {
+                Token::Float(value)
+            } else if word.contains('.') {
+                return Err(error_at(text, Span::new(start, pos), "invalid number"));
+            } else {
+                // Keysym names such as 3270_Duplicate.
+                Token::Ident(word.to_owned())
+            }
+        } else if is_ident_char(c) {
+            while pos < bytes.len() && is_ident_char(bytes[pos]) {
+                pos += 1;
+            }
+            Token::Ident(text[start..pos].to_owned())
+        } else if c == b'"' {
+            pos += 1;
+            let mut string = String::new();
+            loop {
+                let Some(ch) = text[pos..].chars().next() else {
+                    return Err(error_at(text, Span::new(start, pos), "unterminated string"));
+                };
+                pos += ch.len_utf8();
+                match ch {
+                    '"' => break,
+                    '\\' => {
+                        let Some(&escaped) = bytes.get(pos) else {
+                            continue;
+                        };
+                        pos += 1;
+                        match escaped {
+                            b'n' => string.push('\n'),
+                            b't' => string.push('\t'),
+                            b'r' => string.push('\r'),
+                            b'b' => string.push('\u{8}'),
+                            b'f' => string.push('\u{c}'),
+                            b'v' => string.push('\u{b}'),
+                            b'e' => string.push('\u{1b}'),
+                            b'0'..=b'7' => {
+                                let digits_end = (pos..bytes.len().min(pos + 2))
+                                    .find(|&i| !(b'0'..=b'7').contains(&bytes[i]))
+                                    .unwrap_or(bytes.len().min(pos + 2));
+                                let octal = &text[pos - 1..digits_end];
+                                pos = digits_end;
+                                let value = u32::from_str_radix(octal, 8).unwrap_or(0);
+                                string.extend(char::from_u32(value));
+                            }
+                            _ => {
+                                pos -= 1;
+                                let ch = text[pos..].chars().next().unwrap_or('\\');
+                                pos += ch.len_utf8();
+                                string.push(ch);
+                            }
+                        }
+                    }
+                    _ => string.push(ch),
+                }
+            }
+            Token::String(string)
+        } else if c == b'<' {
+            match text[pos + 1..].find(['>', '\n', ' ']) {
+                Some(end) if bytes[pos + 1 + end] == b'>' => {
+                    pos += end + 2;
+                    Token::KeyName(text[start + 1..pos - 1].to_owned())
+                }
+                _ => {
+                    return Err(error_at(
+                        text,
+                        Span::new(start, start + 1),
+                        "unterminated key name",
+                    ))
+                }
+            }
+        } else if b"{}[]();,=+-*/!~.".contains(&c) {
+            pos += 1;
+            Token::Punct(char::from(c))
+        } else {
+            let ch = text[pos..].chars().next().unwrap_or_default();
+            return Err(error_at(
+                text,
+                Span::new(start, start + ch.len_utf8()),
+                format!("unexpected character '{ch}'"),
+            ));
+        };
+        tokens.push((token, Span::new(start, pos)));
+    }
+    tokens.push((Token::Eof, Span::new(bytes.len(), bytes.len())));
+    Ok(tokens)
+}
+
+const FLAGS: [&str; 9] = [
+    "default",
+    "partial",
+    "hidden",
+    "alphanumeric_keys",
+    "modifier_keys",
+    "keypad_keys",
+    "function_keys",
+    "alternate_group",
+    "xkb_semantics",
+];
+
+const MERGE_MODES: [&str; 5] = ["include", "augment", "override", "replace", "alternate"];
+
+struct Parser<'a> {
+    text: &'a str,
+    tokens: Vec<(Token, Span)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].0
+    }
+
+    fn peek_at(&self, offset: usize) -> &Token {
+        let idx = (self.pos + offset).min(self.tokens.len() - 1);
+        &self.tokens[idx].0
+    }
+
+    fn span(&self) -> Span {
+        self.tokens[self.pos].1
+    }
+
+    /// Span of the last consumed token.
+    fn prev_span(&self) -> Span {
+        self.tokens[self.pos.saturating_sub(1)].1
+    }
+
+    fn error(&self, message: impl Into<String>) -> ParseError {
+        let found = match self.peek() {
+            Token::Eof => "end of file".to_owned(),
+            _ => format!("\"{}\"", &self.text[self.span().start..self.span().end]),
+        };
+        error_at(
+            self.text,
+            self.span(),
+            format!("{}, found {found}", message.into()),
+        )
+    }
+
+    fn is_punct(&self, c: char) -> bool {
+        self.peek() == &Token::Punct(c)
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        let found = self.is_punct(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect_punct(&mut self, c: char) -> Result<(), ParseError> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{c}'")))
+        }
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Token::Ident(i) if i.eq_ignore_ascii_case(ident))
+    }
+
+    fn expect_ident(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Ident(ident) => {
+                self.pos += 1;
+                Ok(ident)
+            }
+            _ => Err(self.error("expected identifier")),
+        }
+    }
+
+    fn expect_string(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::String(string) => {
+                self.pos += 1;
+                Ok(string)
+            }
+            _ => Err(self.error("expected string")),
+        }
+    }
+
+    fn expect_key_name(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::KeyName(name) => {
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(self.error("expected key name")),
+        }
+    }
+
+    fn expect_u32(&mut self) -> Result<u32, ParseError> {
+        match *self.peek() {
+            Token::Integer(value, _) if u32::try_from(value).is_ok() => {
+                self.pos += 1;
+                Ok(value as u32)
+            }
+            _ => Err(self.error("expected integer")),
+        }
+    }
+
+    fn flags(&mut self) -> Vec<String> {
+        let mut flags = Vec::new();
+        while let Token::Ident(ident) = self.peek() {
+            if !FLAGS.contains(&ident.as_str()) {
+                break;
+            }
+            flags.push(ident.clone());
+            self.pos += 1;
+        }
+        flags
+    }
+
+    fn optional_name(&mut self) -> Option<String> {
+        match self.peek().clone() {
+            Token::String(name) => {
+                self.pos += 1;
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    fn keymap_file(&mut self) -> Result<KeymapFile, ParseError> {
+        let start = self.span();
+        let flags = self.flags();
+        if !self.is_ident("xkb_keymap") && !self.is_ident("xkb_layout") {
+            return Err(self.error("expected \"xkb_keymap\""));
+        }
+        self.pos += 1;
+        let name = self.optional_name();
+        self.expect_punct('{')?;
+        let mut sections = Vec::new();
+        while !self.eat_punct('}') {
+            sections.push(self.section()?);
+        }
+        self.eat_punct(';');
+        Ok(KeymapFile {
+            flags,
+            name,
+            sections,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    fn section(&mut self) -> Result<Section, ParseError> {
+        let start = self.span();
+        let flags = self.flags();
+        let kind = match self.peek() {
+            Token::Ident(keyword) => SectionKind::from_keyword(keyword),
+            _ => None,
+        };
+        let Some(kind) = kind else {
+            return Err(self.error("expected section"));
+        };
+        self.pos += 1;
+        let name = self.optional_name();
+        self.expect_punct('{')?;
+        let mut statements = Vec::new();
+        while !self.eat_punct('}') {
+            statements.push(self.statement()?);
+        }
+        self.expect_punct(';')?;
+        Ok(Section {
+            flags,
+            kind,
+            name,
+            statements,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    fn statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.span();
+        let kind = self.statement_kind()?;
+        self.expect_punct(';')?;
+        Ok(Statement {
+            kind,
+            span: start.to(self.prev_span()),
+        })
+    }
+
+    fn statement_kind(&mut self) -> Result<StatementKind, ParseError> {
+        let keyword = match self.peek().clone() {
+            Token::KeyName(name) => {
+                self.pos += 1;
+                self.expect_punct('=')?;
+                let value = self.expr()?;
+                return Ok(StatementKind::Keycode { name, value });
+            }
+            Token::Ident(ident) => ident.to_ascii_lowercase(),
+            _ => String::new(),
+        };
+        let next = self.peek_at(1).clone();
+        match (keyword.as_str(), next) {
+            (merge, Token::String(file)) if MERGE_MODES.contains(&merge) => {
+                self.pos += 2;
+                Ok(StatementKind::Include {
+                    merge: merge.to_owned(),
+                    file,
+                })
+            }
+            ("alias", Token::KeyName(alias)) => {
+                self.pos += 2;
+                self.expect_punct('=')?;
+                let real = self.expect_key_name()?;
+                Ok(StatementKind::Alias { alias, real })
+            }
+            ("virtual", _) if matches!(self.peek_at(1), Token::Ident(i) if i == "indicator") => {
+                self.pos += 2;
+                let (index, name) = self.indicator_name()?;
+                Ok(StatementKind::IndicatorName {
+                    index,
+                    name,
+                    is_virtual: true,
+                })
+            }
+            ("indicator", Token::Integer(..)) => {
+                self.pos += 1;
+                let (index, name) = self.indicator_name()?;
+                Ok(StatementKind::IndicatorName {
+                    index,
+                    name,
+                    is_virtual: false,
+                })
+            }
+            ("indicator", Token::String(name)) => {
+                self.pos += 2;
+                let body = self.body()?;
+                Ok(StatementKind::IndicatorMap { name, body })
+            }
+            ("virtual_modifiers", _) => {
+                self.pos += 1;
+                let mut vmods = vec![self.var_def()?];
+                while self.eat_punct(',') {
+                    vmods.push(self.var_def()?);
+                }
+                Ok(StatementKind::VirtualModifiers(vmods))
+            }
+            ("type", Token::String(name)) => {
+                self.pos += 2;
+                let body = self.body()?;
+                Ok(StatementKind::KeyType { name, body })
+            }
+            ("interpret", next) if next != Token::Punct('.') => {
+                self.pos += 1;
+                let keysym = self.primary()?;
+                let predicate = if self.eat_punct('+') {
+                    Some(self.primary()?)
+                } else {
+                    None
+                };
+                let body = self.body()?;
+                Ok(StatementKind::Interpret {
+                    keysym,
+                    predicate,
+                    body,
+                })
+            }
+            ("key", Token::KeyName(name)) => {
+                self.pos += 2;
+                self.expect_punct('{')?;
+                let mut body = Vec::new();
+                if !self.is_punct('}') {
+                    body.push(self.var_def()?);
+                    while self.eat_punct(',') {
+                        if self.is_punct('}') {
+                            break;
+                        }
+                        body.push(self.var_def()?);
+                    }
+                }
+                self.expect_punct('}')?;
+                Ok(StatementKind::Key { name, body })
+            }
+            ("modifier_map" | "mod_map" | "modmap", _) => {
+                self.pos += 1;
+                let modifier = self.expect_ident()?;
+                let keys = self.list('{', '}')?;
+                Ok(StatementKind::ModifierMap { modifier, keys })
+            }
+            ("group", Token::Integer(..)) => {
+                self.pos += 1;
+                let group = self.expect_u32()?;
+                self.expect_punct('=')?;
+                let value = self.expr()?;
+                Ok(StatementKind::GroupCompat { group, value })
+            }
+            _ => Ok(StatementKind::Var(self.var_def()?)),
+        }
+    }
+
+    /// Parse `N = "name"` after the `indicator` keyword.
+    fn indicator_name(&mut self) -> Result<(u32, String), ParseError> {
+        let index = self.expect_u32()?;
+        self.expect_punct('=')?;
+        let name = self.expect_string()?;
+        Ok((index, name))
+    }
+
+    /// Parse `{ var; var; }`.
+    fn body(&mut self) -> Result<Vec<VarDef>, ParseError> {
+        self.expect_punct('{')?;
+        let mut body = Vec::new();
+        while !self.eat_punct('}') {
+            body.push(self.var_def()?);
+            self.expect_punct(';')?;
+        }
+        Ok(body)
+    }
+
+    fn var_def(&mut self) -> Result<VarDef, ParseError> {
+        let start = self.span();
+        let expr = self.expr()?;
+        if !self.eat_punct('=') {
+            return Ok(VarDef {
+                name: None,
+                span: expr.span,
+                value: expr,
+            });
+        }
+        if !matches!(
+            expr.kind,
+            ExprKind::Ident(_) | ExprKind::FieldRef { .. } | ExprKind::ArrayRef { .. }
+        ) {
+            return Err(error_at(self.text, expr.span, "invalid variable name"));
+        }
+        let value = self.expr()?;
+        Ok(VarDef {
+            name: Some(expr),
+            span: start.to(value.span),
+            value,
+        })
+    }
+
+    /// Parse a delimited, comma-separated list of expressions.
+    fn list(&mut self, open: char, close: char) -> Result<Vec<Expr>, ParseError> {
+        self.expect_punct(open)?;
+        let mut items = Vec::new();
+        if self.eat_punct(close) {
+            return Ok(items);
+        }
+        loop {
+            items.push(self.expr()?);
+            if self.eat_punct(close) {
+                return Ok(items);
+            }
+            self.expect_punct(',')?;
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.binary(1)
+    }
+
+    fn binary(&mut self, min_precedence: u8) -> Result<Expr, ParseError> {
+        let mut lhs = if min_precedence > 2 {
+            self.unary()?
+        } else {
+            self.binary(min_precedence + 1)?
+        };
+        loop {
+            let op = match self.peek() {
+                Token::Punct('+') => BinaryOp::Add,
+                Token::Punct('-') => BinaryOp::Subtract,
+                Token::Punct('*') => BinaryOp::Multiply,
+                Token::Punct('/') => BinaryOp::Divide,
+                _ => return Ok(lhs),
+            };
+            if op.precedence() != min_precedence {
+                return Ok(lhs);
+            }
+            self.pos += 1;
+            let rhs = if min_precedence >= 2 {
+                self.unary()?
+            } else {
+                self.binary(min_precedence + 1)?
+            };
+            lhs = Expr {
+                span: lhs.span.to(rhs.span),
+                kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            };
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.span();
+        let op = match self.peek() {
+            Token::Punct('-') => UnaryOp::Negate,
+            Token::Punct('+') => UnaryOp::Plus,
+            Token::Punct('!') => UnaryOp::Not,
+            Token::Punct('~') => UnaryOp::Invert,
+            _ => return self.primary(),
+        };
+        self.pos += 1;
+        let expr = self.unary()?;
+        Ok(Expr {
+            span: start.to(expr.span),
+            kind: ExprKind::Unary(op, Box::new(expr)),
+        })
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParseError> {
+        let start = self.span();
+        let kind = match self.peek().clone() {
+            Token::Integer(value, hex) => {
+                self.pos += 1;
+                ExprKind::Integer { value, hex }
+            }
+            Token::Float(value) => {
+                self.pos += 1;
+                ExprKind::Float(value)
+            }
+            Token::String(string) => {
+                self.pos += 1;
+                ExprKind::String(string)
+            }
+            Token::KeyName(name) => {
+                self.pos += 1;
+                ExprKind::KeyName(name)
+            }
+            Token::Punct('(') => {
+                self.pos += 1;
+                let expr = self.expr()?;
+                self.expect_punct(')')?;
+                return Ok(Expr {
+                    kind: expr.kind,
+                    span: start.to(self.prev_span()),
+                });
+            }
+            Token::Punct('[') => ExprKind::List(self.list('[', ']')?),
+            Token::Punct('{') => ExprKind::Braced(self.list('{', '}')?),
+            Token::Ident(ident) => {
+                self.pos += 1;
+                if self.eat_punct('(') {
+                    let mut args = Vec::new();
+                    if !self.eat_punct(')') {
+                        loop {
+                            args.push(self.var_def()?);
+                            if self.eat_punct(')') {
+                                break;
+                            }
+                            self.expect_punct(',')?;
+                        }
+                    }
+                    ExprKind::Call { name: ident, args }
+                } else {
+                    let (elem, field) = if self.eat_punct('.') {
+                        (Some(ident), self.expect_ident()?)
+                    } else {
+                        (None, ident)
+                    };
+                    if self.eat_punct('[') {
+                        let index = self.expr()?;
+                        self.expect_punct(']')?;
+                        ExprKind::ArrayRef {
+                            elem,
+                            field,
+                            index: Box::new(index),
+                        }
+                    } else if let Some(elem) = elem {
+                        ExprKind::FieldRef { elem, field }
+                    } else {
+                        ExprKind::Ident(field)
+                    }
+                }
+            }
+            _ => return Err(self.error("expected expression")),
+        };
+        Ok(Expr {
+            kind,
+            span: start.to(self.prev_span()),
+        })
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, string: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in string.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{c}")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\{:03o}", u32::from(c))?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, items: &[Expr]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str(", ")?;
+        }
+        write!(f, "{item}")?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ExprKind::Ident(ident) => f.write_str(ident),
+            ExprKind::Integer { value, hex: true } if *value >= 0 => write!(f, "{value:#x}"),
+            ExprKind::Integer { value, .. } => write!(f, "{value}"),
+            ExprKind::Float(value) => write!(f, "{value:?}"),
+            ExprKind::String(string) => write_string(f, string),
+            ExprKind::KeyName(name) => write!(f, "<{name}>"),
+            ExprKind::Unary(op, expr) => {
+                let op = match op {
+                    UnaryOp::Negate => '-',
+                    UnaryOp::Plus => '+',
+                    UnaryOp::Not => '!',
+                    UnaryOp::Invert => '~',
+                };
+                match expr.kind {
+                    ExprKind::Binary(..) => write!(f, "{op}({expr})"),
+                    _ => write!(f, "{op}{expr}"),
+                }
+            }
+            ExprKind::Binary(op, lhs, rhs) => {
+                let operand = |f: &mut fmt::Formatter<'_>, expr: &Expr, min: u8| match expr.kind {
+                    ExprKind::Binary(op, ..) if op.precedence() < min => write!(f, "({expr})"),
+                    _ => write!(f, "{expr}"),
+                };
+                let symbol = match op {
+                    BinaryOp::Add => '+',
+                    BinaryOp::Subtract => '-',
+                    BinaryOp::Multiply => '*',
+                    BinaryOp::Divide => '/',
+                };
+                operand(f, lhs, op.precedence())?;
+                write!(f, "{symbol}")?;
+                operand(f, rhs, op.precedence() + 1)
+            }
+            ExprKind::Call { name, args } => {
+                write!(f, "{name}(")?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                f.write_str(")")
+            }
+            ExprKind::FieldRef { elem, field } => write!(f, "{elem}.{field}"),
+            ExprKind::ArrayRef { elem, field, index } => match elem {
+                Some(elem) => write!(f, "{elem}.{field}[{index}]"),
+                None => write!(f, "{field}[{index}]"),
+            },
+            ExprKind::List(items) => {
+                f.write_str("[ ")?;
+                write_list(f, items)?;
+                f.write_str(" ]")
+            }
+            ExprKind::Braced(items) => {
+                f.write_str("{ ")?;
+                write_list(f, items)?;
+                f.write_str(" }")
+            }
+        }
+    }
+}
+
+/// Writes `name=value`, or `name= value` with the alternate flag (`{:#}`)
+/// as in statements.
+impl fmt::Display for VarDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) if f.alternate() => write!(f, "{name}= {}", self.value),
+            Some(name) => write!(f, "{name}={}", self.value),
+            None => write!(f, "{}", self.value),
+        }
+    }
+}
+
+fn write_body(f: &mut fmt::Formatter<'_>, body: &[VarDef]) -> fmt::Result {
+    f.write_str(" {\n")?;
+    for var in body {
+        writeln!(f, "\t\t{var:#};")?;
+    }
+    f.write_str("\t}")
+}
+
+/// Writes the statement with one level of indentation, and without the
+/// final semicolon.
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("\t")?;
+        match &self.kind {
+            StatementKind::Include { merge, file } => {
+                write!(f, "{merge} ")?;
+                write_string(f, file)
+            }
+            StatementKind::Var(var) => write!(f, "{var:#}"),
+            StatementKind::VirtualModifiers(vmods) => {
+                f.write_str("virtual_modifiers ")?;
+                for (i, vmod) in vmods.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{vmod}")?;
+                }
+                Ok(())
+            }
+            StatementKind::Keycode { name, value } => {
+                write!(f, "{:<20} = {value}", format!("<{name}>"))
+            }
+            StatementKind::Alias { alias, real } => {
+                write!(f, "alias {:<14} = <{real}>", format!("<{alias}>"))
+            }
+            StatementKind::IndicatorName {
+                index,
+                name,
+                is_virtual,
+            } => {
+                if *is_virtual {
+                    f.write_str("virtual ")?;
+                }
+                write!(f, "indicator {index} = ")?;
+                write_string(f, name)
+            }
+            StatementKind::KeyType { name, body } => {
+                f.write_str("type ")?;
+                write_string(f, name)?;
+                write_body(f, body)
+            }
+            StatementKind::Interpret {
+                keysym,
+                predicate,
+                body,
+            } => {
+                write!(f, "interpret {keysym}")?;
+                if let Some(predicate) = predicate {
+                    write!(f, "+{predicate}")?;
+                }
+                write_body(f, body)
+            }
+            StatementKind::IndicatorMap { name, body } => {
+                f.write_str("indicator ")?;
+                write_string(f, name)?;
+                write_body(f, body)
+            }
+            StatementKind::Key { name, body } => {
+                write!(f, "key {:<20} {{", format!("<{name}>"))?;
+                match body.as_slice() {
+                    [var] if var.name.is_none() => write!(f, "\t{var} }}"),
+                    _ => {
+                        for (i, var) in body.iter().enumerate() {
+                            let sep = if i + 1 < body.len() { "," } else { "" };
+                            write!(f, "\n\t\t{var:#}{sep}")?;
+                        }
+                        f.write_str("\n\t}")
+                    }
+                }
+            }
+            StatementKind::ModifierMap { modifier, keys } => {
+                write!(f, "modifier_map {modifier} {{ ")?;
+                write_list(f, keys)?;
+                f.write_str(" }")
+            }
+            StatementKind::GroupCompat { group, value } => write!(f, "group {group} = {value}"),
+        }
+    }
+}
+
+impl fmt::Display for Section {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for flag in &self.flags {
+            write!(f, "{flag} ")?;
+        }
+        f.write_str(self.kind.keyword())?;
+        if let Some(name) = &self.name {
+            f.write_str(" ")?;
+            write_string(f, name)?;
+        }
+        f.write_str(" {\n")?;
+        for statement in &self.statements {
+            writeln!(f, "{statement};")?;
+        }
+        f.write_str("};\n")
+    }
+}
+
+impl fmt::Display for KeymapFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for flag in &self.flags {
+            write!(f, "{flag} ")?;
+        }
+        f.write_str("xkb_keymap")?;
+        if let Some(name) = &self.name {
+            f.write_str(" ")?;
+            write_string(f, name)?;
+        }
+        f.write_str(" {\n")?;
+        for section in &self.sections {
+            writeln!(f, "{section}")?;
+        }
+        f.write_str("};\n")
+    }
+}
+
+#[test]
+fn text_round_trip() {
+    use super::{Context, KEYMAP_COMPILE_NO_FLAGS};
+
+    let context = Context::new(0);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us,de",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let text = keymap.get_as_string(KEYMAP_FORMAT_TEXT_V1);
+    let file = parse(&text).unwrap();
+    assert_eq!(file.sections.len(), 4);
+    let reparsed = Keymap::new_from_string(
+        &context,
+        file.to_string(),
+        KEYMAP_FORMAT_TEXT_V1,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    assert_eq!(reparsed.get_as_string(KEYMAP_FORMAT_TEXT_V1), text);
+}
@@ -0,0 +1,186 @@
+use super::{KeyDirection, Keycode, Keymap, State, StateChanges};
+
+/// Keyboard state object which tracks the set of pressed keys.
+///
+/// `State::update_key()` requires a consistent series of key events: a key
+/// press must be matched by a release, and a key pressed twice must be
+/// released twice. When events get lost (VT switches, focus changes,
+/// device resets...), this leads to "stuck modifiers".
+///
+/// `TrackedState` records the keys that are currently pressed, ignores
+/// duplicated presses and releases of keys that are not pressed, and can
+/// synthesize the releases needed to bring the state back to a sane value
+/// with `release_all()` or `resync()`.
+pub struct TrackedState {
+    state: State,
+    pressed: Vec<Keycode>,
+}
+
+impl TrackedState {
+    /// Create a new tracked keyboard state object from a keymap.
+    #[must_use]
+    pub fn new(keymap: &Keymap) -> TrackedState {
+        TrackedState::from_state(State::new(keymap))
+    }
+
+    /// Start tracking the pressed keys of an existing state object.
+    ///
+    /// The state is assumed to have no key pressed.
+    #[must_use]
+    pub fn from_state(state: State) -> TrackedState {
+        TrackedState {
+            state,
+            pressed: Vec::new(),
+        }
+    }
+
+    /// Get the underlying keyboard state object.
+    #[must_use]
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Stop tracking and get the underlying keyboard state object.
+    #[must_use]
+    pub fn into_state(self) -> State {
+        self.state
+    }
+
+    /// Get the keys that are currently pressed, in the order in which they
+    /// were pressed.
+    #[must_use]
+    pub fn pressed_keys(&self) -> &[Keycode] {
+        &self.pressed
+    }
+
+    /// Whether a key is currently pressed.
+    #[must_use]
+    pub fn is_pressed(&self, key: Keycode) -> bool {
+        self.pressed.contains(&key)
+    }
+
+    /// Update the keyboard state to reflect a given key being pressed or
+    /// released.
+    ///
+    /// Presses of keys that are already pressed and releases of keys that
+    /// are not pressed are ignored, and reported as no change.
+    pub fn update_key(&mut self, key: Keycode, direction: KeyDirection) -> StateChanges {
+        let old = self.state.values();
+        let components = match direction {
+            KeyDirection::Down if !self.is_pressed(key) => {
+                self.pressed.push(key);
+                self.state.update_key(key, KeyDirection::Down)
+            }
+            KeyDirection::Up if self.is_pressed(key) => {
+                self.pressed.retain(|&k| k != key);
+                self.state.update_key(key, KeyDirection::Up)
+            }
+            _ => 0,
+        };
+        self.state.changes_since(old, components)
+    }
+
+    /// Release all the pressed keys, in the reverse order of their presses.
+    ///
+    /// Use this when key events may have been missed, e.g. when the
+    /// keyboard focus is lost or on VT switch, so that depressed modifiers
+    /// recover. Locked modifiers and layouts are left untouched.
+    pub fn release_all(&mut self) -> StateChanges {
+        let old = self.state.values();
+        let mut components = 0;
+        while let Some(key) = self.pressed.pop() {
+            components |= self.state.update_key(key, KeyDirection::Up);
+        }
+        self.state.changes_since(old, components)
+    }
+
+    /// Synchronize the state with an authoritative set of pressed keys, as
+    /// reported e.g. by the `EVIOCGKEY` ioctl of evdev.
+    ///
+    /// Tracked keys missing from `pressed` are released first, then keys of
+    /// `pressed` that are not tracked yet are pressed in order.
+    ///
+    /// Note that pressing a key may have lasting effects, e.g. pressing
+    /// Caps Lock toggles the Caps Lock modifier.
+    pub fn resync(&mut self, pressed: &[Keycode]) -> StateChanges {
+        let old = self.state.values();
+        let mut components = 0;
+        let released: Vec<Keycode> = self
+            .pressed
+            .iter()
+            .rev()
+            .filter(|key| !pressed.contains(key))
+            .copied()
+            .collect();
+        for key in released {
+            self.pressed.retain(|&k| k != key);
+            components |= self.state.update_key(key, KeyDirection::Up);
+        }
+        for &key in pressed {
+            if !self.is_pressed(key) {
+                self.pressed.push(key);
+                components |= self.state.update_key(key, KeyDirection::Down);
+            }
+        }
+        self.state.changes_since(old, components)
+    }
+}
+
+#[test]
+fn tracked_state_recovers() {
+    use super::{
+        Context, CONTEXT_NO_FLAGS, KEYMAP_COMPILE_NO_FLAGS, MOD_NAME_CAPS, MOD_NAME_CTRL,
+        MOD_NAME_SHIFT, STATE_MODS_DEPRESSED, STATE_MODS_LOCKED,
+    };
+
+    let context = Context::new(CONTEXT_NO_FLAGS);
+    let keymap = Keymap::new_from_names(
+        &context,
+        "evdev",
+        "pc105",
+        "us",
+        "",
+        None,
+        KEYMAP_COMPILE_NO_FLAGS,
+    )
+    .unwrap();
+    let key = |name| keymap.key_by_name(name).unwrap();
+    let mask = |name| 1 << keymap.mod_get_index(name);
+    let (shift, ctrl, caps) = (key("LFSH"), key("LCTL"), key("CAPS"));
+    let mut state = TrackedState::new(&keymap);
+    let depressed = |state: &TrackedState| state.state().serialize_mods(STATE_MODS_DEPRESSED);
+
+    state.update_key(shift, KeyDirection::Down);
+    assert!(state.update_key(shift, KeyDirection::Down).is_empty());
+    state.update_key(caps, KeyDirection::Down);
+    state.update_key(caps, KeyDirection::Up);
+    assert!(state.update_key(caps, KeyDirection::Up).is_empty());
+    state.update_key(ctrl, KeyDirection::Down);
+    assert_eq!(state.pressed_keys(), [shift, ctrl]);
+
+    let changes = state.release_all();
+    assert_eq!(
+        changes.old.depressed_mods,
+        mask(MOD_NAME_SHIFT) | mask(MOD_NAME_CTRL)
+    );
+    assert_eq!(depressed(&state), 0);
+    assert!(state.pressed_keys().is_empty());
+    // Locked modifiers are left untouched.
+    assert_eq!(
+        state.state().serialize_mods(STATE_MODS_LOCKED),
+        mask(MOD_NAME_CAPS)
+    );
+    assert!(state.release_all().is_empty());
+
+    state.update_key(shift, KeyDirection::Down);
+    state.resync(&[ctrl, shift]);
+    assert_eq!(state.pressed_keys(), [shift, ctrl]);
+    assert_eq!(
+        depressed(&state),
+        mask(MOD_NAME_SHIFT) | mask(MOD_NAME_CTRL)
+    );
+    let changes = state.resync(&[ctrl]);
+    assert_eq!(changes.new.depressed_mods, mask(MOD_NAME_CTRL));
+    assert!(!state.is_pressed(shift) && state.is_pressed(ctrl));
+    assert!(state.resync(&[ctrl]).is_empty());
+}